@@ -0,0 +1,94 @@
+use num::complex::Complex;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[path = "../src/fft.rs"]
+mod fft;
+
+const PI: f32 = std::f32::consts::PI;
+const I: Complex<f32> = Complex { re: 0.0, im: 1.0 };
+const SIZES: [usize; 5] = [512, 1024, 2048, 4096, 8192];
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+// the recursive complex FFT that audio::process used before the real-input plans were introduced
+fn recursive_fft(x: &mut [Complex<f32>], y: &mut [Complex<f32>], n: usize, step: usize) {
+    if n == 1 {
+        y[0] = x[0];
+        return;
+    }
+    recursive_fft(x, y, n / 2, step * 2);
+    recursive_fft(&mut x[step..], &mut y[(n / 2)..], n / 2, step * 2);
+    for k in 0..(n / 2) {
+        let t = (-2.0 * I * PI * (k as f32) / (n as f32)).exp() * y[k + n / 2];
+        let temp = y[k];
+        y[k] = temp + t;
+        y[k + n / 2] = temp - t;
+    }
+}
+
+fn recursive(samples: &[f32]) -> Vec<Complex<f32>> {
+    let n = samples.len();
+    let mut complex_samples = samples
+        .iter()
+        .map(|&x| Complex::new(x, 0.0))
+        .collect::<Vec<_>>();
+    let mut transformed_samples = complex_samples.clone();
+    recursive_fft(&mut complex_samples, &mut transformed_samples, n, 1);
+    transformed_samples.truncate(n / 2 + 1);
+
+    transformed_samples
+}
+
+/// Run f repeatedly for at least MIN_DURATION and return the mean time of a single run
+fn time_per_iter<F: FnMut()>(mut f: F) -> Duration {
+    let (start, mut iters) = (Instant::now(), 0);
+    while start.elapsed() < MIN_DURATION {
+        f();
+        iters += 1;
+    }
+
+    start.elapsed() / iters
+}
+
+fn main() {
+    let mut planner = fft::FftPlanner::new();
+    println!(
+        "{:>6} {:>14} {:>14} {:>8} {:>10}",
+        "size", "recursive", "real plan", "speedup", "max error"
+    );
+    for n in SIZES {
+        // a few sines with some noise-like content, similar to what comes out of a sound card
+        let samples = (0..n)
+            .map(|i| {
+                let t = i as f32 / 48000.0;
+                0.5 * (2.0 * PI * 110.0 * t).sin()
+                    + 0.25 * (2.0 * PI * 1760.0 * t).sin()
+                    + 0.1 * ((i * 7919 % 211) as f32 / 211.0 - 0.5)
+            })
+            .collect::<Vec<_>>();
+
+        let expected = recursive(&samples);
+        let max_error = planner
+            .plan(n)
+            .transform(&samples)
+            .iter()
+            .zip(expected.iter())
+            .map(|(a, b)| (a - b).norm())
+            .fold(0.0, f32::max);
+
+        let t_recursive = time_per_iter(|| {
+            black_box(recursive(black_box(&samples)));
+        });
+        let t_plan = time_per_iter(|| {
+            black_box(planner.plan(n).transform(black_box(&samples)));
+        });
+        println!(
+            "{:>6} {:>11.2} us {:>11.2} us {:>7.1}x {:>10.2e}",
+            n,
+            t_recursive.as_secs_f64() * 1e6,
+            t_plan.as_secs_f64() * 1e6,
+            t_recursive.as_secs_f64() / t_plan.as_secs_f64(),
+            max_error
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use crate::envelope::smoothing_coeff;
+
+/// Automatic gain control, following the peak amplitude of the spectrum and choosing the gain
+/// that brings that peak to the target level
+#[derive(Debug)]
+pub struct Agc {
+    attack: f32,
+    release: f32,
+    target: f32,
+    max_gain: f32,
+    level: f32,
+}
+
+impl Agc {
+    /// Return an AGC for frames analyzed every frame_duration seconds, with attack and release times in seconds,
+    /// the target is the amplitude that the peak should be amplified to
+    pub fn new(
+        frame_duration: f32,
+        attack_time: f32,
+        release_time: f32,
+        target: f32,
+        max_gain: f32,
+    ) -> Self {
+        Agc {
+            attack: smoothing_coeff(frame_duration, attack_time),
+            release: smoothing_coeff(frame_duration, release_time),
+            target,
+            max_gain,
+            level: 0.0,
+        }
+    }
+
+    /// Feed the (not yet amplified) spectrum of the next frame, returning the gain to apply to it
+    pub fn update(&mut self, spectrum: &[f32]) -> f32 {
+        let peak = spectrum.iter().copied().fold(0.0, f32::max);
+        let coeff = if peak > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level += coeff * (peak - self.level);
+
+        if self.level > 0.0 {
+            (self.target / self.level).min(self.max_gain)
+        } else {
+            self.max_gain
+        }
+    }
+}
@@ -1,83 +1,708 @@
-use num::complex::Complex;
+use crate::config::{AmplitudeMapping, BandScale, Downmix, Window};
+use crate::cqt::ConstantQ;
+use crate::envelope::Envelopes;
+use crate::fft::FftPlanner;
 use palette::Hwb;
 
-const PI: f32 = std::f32::consts::PI;
-const I: Complex<f32> = Complex { re: 0.0, im: 1.0 };
+/// Mix the samples of all channels from a single frame into one sample
+pub fn downmix(frame: &[f32], downmix: Downmix) -> f32 {
+    let n_channels = frame.len();
+    // a mono input has the same left and right channel
+    let (left, right) = (frame[0], frame[1.min(n_channels - 1)]);
+    match downmix {
+        Downmix::Mean => frame.iter().sum::<f32>() / (n_channels as f32),
+        Downmix::Rms => {
+            let rms = (frame.iter().map(|x| x * x).sum::<f32>() / (n_channels as f32)).sqrt();
+            // signed like the mean (or the first channel, if the channels cancel out),
+            // as a magnitude alone would rectify the signal
+            let sum = frame.iter().sum::<f32>();
+            if sum != 0.0 {
+                rms.copysign(sum)
+            } else {
+                rms.copysign(frame[0])
+            }
+        }
+        Downmix::Mid => 0.5 * (left + right),
+        Downmix::Side => 0.5 * (left - right),
+        Downmix::Channel(channel) => frame[channel],
+    }
+}
+
+/// Coefficients of the window function of length n
+pub fn window_coefficients(window: Window, n: usize) -> Vec<f32> {
+    // sums of cosines, see https://en.wikipedia.org/wiki/Window_function#Cosine-sum_windows
+    let cosine_terms: &[f64] = match window {
+        Window::Rectangular => &[1.0],
+        Window::Hann => &[0.5, 0.5],
+        Window::Hamming => &[0.54, 0.46],
+        Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+        Window::FlatTop => &[
+            0.21557895,
+            0.41663158,
+            0.277263158,
+            0.083578947,
+            0.006947368,
+        ],
+    };
+    (0..n)
+        .map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * (i as f64) / (n as f64);
+            cosine_terms
+                .iter()
+                .enumerate()
+                .map(|(k, a)| match k % 2 {
+                    0 => a * (k as f64 * phase).cos(),
+                    _ => -a * (k as f64 * phase).cos(),
+                })
+                .sum::<f64>() as f32
+        })
+        .collect::<Vec<_>>()
+}
+
+/// Coefficients of a window function, recomputed only when the length of the analyzed block changes
+#[derive(Debug)]
+pub struct WindowTable {
+    window: Window,
+    coefficients: Vec<f32>,
+    coherent_gain: f32,
+    windowed: Vec<f32>,
+}
 
-fn fft(x: &mut [Complex<f32>], y: &mut [Complex<f32>], n: usize, step: usize) {
-    if n == 1 {
-        y[0] = x[0];
-        return;
+impl WindowTable {
+    pub fn new(window: Window) -> Self {
+        WindowTable {
+            window,
+            coefficients: Vec::new(),
+            coherent_gain: 1.0,
+            windowed: Vec::new(),
+        }
     }
-    fft(x, y, n / 2, step * 2);
-    fft(&mut x[step..], &mut y[(n / 2)..], n / 2, step * 2);
-    for k in 0..(n / 2) {
-        let t = (-2.0 * I * PI * (k as f32) / (n as f32)).exp() * y[k + n / 2];
-        let temp = y[k];
-        y[k] = temp + t;
-        y[k + n / 2] = temp - t;
+
+    fn resize(&mut self, n: usize) {
+        self.coefficients = window_coefficients(self.window, n);
+        // the mean of the window is by how much it scales the amplitude of a sine in the middle of a bin
+        self.coherent_gain = self.coefficients.iter().sum::<f32>() / (n.max(1) as f32);
+        self.windowed = vec![0.0; n];
+    }
+
+    /// Multiply the samples by the window, returning the result and the window's coherent gain
+    pub fn apply(&mut self, samples: &[f32]) -> (&[f32], f32) {
+        if self.coefficients.len() != samples.len() {
+            self.resize(samples.len());
+        }
+        for ((y, x), w) in self
+            .windowed
+            .iter_mut()
+            .zip(samples.iter())
+            .zip(self.coefficients.iter())
+        {
+            *y = x * w;
+        }
+
+        (&self.windowed, self.coherent_gain)
     }
 }
 
-pub fn process(samples: Vec<f32>, k: f32) -> Vec<f32> {
-    let mut n = samples.len();
-    let mut complex_samples = samples
-        .into_iter()
-        .map(|x| Complex::new(x, 0.0))
-        .collect::<Vec<_>>();
-    complex_samples.append(&mut vec![Complex::new(0.0, 0.0); n.next_power_of_two() - n]);
-    n = complex_samples.len();
-    let mut transformed_samples = complex_samples.clone();
-    fft(&mut complex_samples, &mut transformed_samples, n, 1);
+/// Ring buffer of the most recent samples, from which overlapping analysis frames are taken every hop_size samples
+#[derive(Debug)]
+pub struct SampleBuffer {
+    samples: Vec<f32>,
+    pos: usize,
+    hop_size: usize,
+    since_last_frame: usize,
+    frame: Vec<f32>,
+}
 
-    // normalize and apply a sigmoid-type function (x / sqrt(1 + x^2)) so that amplitudes become numbers between 0 and 1
-    let root_n = (n as f32).sqrt();
+impl SampleBuffer {
+    pub fn new(frame_len: usize, hop_size: usize) -> Self {
+        SampleBuffer {
+            samples: vec![0.0; frame_len],
+            pos: 0,
+            hop_size,
+            since_last_frame: 0,
+            frame: vec![0.0; frame_len],
+        }
+    }
+
+    /// Add a sample to the buffer, returning true if a new frame is due
+    pub fn push(&mut self, sample: f32) -> bool {
+        self.samples[self.pos] = sample;
+        self.pos = (self.pos + 1) % self.samples.len();
+        self.since_last_frame += 1;
+        if self.since_last_frame == self.hop_size {
+            self.since_last_frame = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the last frame_len samples, from the oldest to the newest
+    pub fn frame(&mut self) -> &[f32] {
+        let (newer, older) = self.samples.split_at(self.pos);
+        self.frame[..older.len()].copy_from_slice(older);
+        self.frame[older.len()..].copy_from_slice(newer);
+
+        &self.frame
+    }
+}
+
+/// Compute the normalized amplitude spectrum of the samples
+pub fn process(samples: &[f32], window: &mut WindowTable, planner: &mut FftPlanner) -> Vec<f32> {
+    let n = samples.len().next_power_of_two().max(2);
+    let (windowed_samples, coherent_gain) = window.apply(samples);
+    let transformed_samples = planner.plan(n).transform(windowed_samples);
+
+    // normalize, also undoing the attenuation caused by the window
+    let norm = (n as f32).sqrt() * coherent_gain;
     transformed_samples
-        .into_iter()
+        .iter()
         .take(n / 2)
-        .map(|z| {
-            let x = k * z.norm() / root_n;
-            x / (1.0 + x * x).sqrt()
+        .map(|z| z.norm() / norm)
+        .collect::<Vec<_>>()
+}
+
+/// Frequencies (in Hz) covered by the bins of a spectrum, bin i covers edges[i]..edges[i + 1]
+#[derive(Debug, Clone)]
+pub struct Bins {
+    pub centers: Vec<f32>,
+    pub edges: Vec<f32>,
+}
+
+impl Bins {
+    /// Bins of an FFT, equally wide and centered at multiples of hz_per_bin
+    pub fn linear(n_bins: usize, hz_per_bin: f32) -> Self {
+        Bins {
+            centers: (0..n_bins)
+                .map(|i| i as f32 * hz_per_bin)
+                .collect::<Vec<_>>(),
+            edges: (0..=n_bins)
+                .map(|i| ((i as f32 - 0.5) * hz_per_bin).max(0.0))
+                .collect::<Vec<_>>(),
+        }
+    }
+
+    /// Bins with the given (increasing) centers, the edges being halfway between them on a log scale
+    pub fn geometric(centers: Vec<f32>) -> Self {
+        let n = centers.len();
+        let mut edges = centers
+            .windows(2)
+            .map(|pair| (pair[0] * pair[1]).sqrt())
+            .collect::<Vec<_>>();
+        match n {
+            0 => edges.push(0.0),
+            1 => edges = vec![centers[0] / 2f32.sqrt(), centers[0] * 2f32.sqrt()],
+            _ => {
+                edges.insert(0, centers[0] * centers[0] / edges[0]);
+                edges.push(centers[n - 1] * centers[n - 1] / edges[n - 2]);
+            }
+        }
+
+        Bins { centers, edges }
+    }
+
+    /// Indices of the bins centered between min_freq and max_freq
+    pub fn range(&self, min_freq: f32, max_freq: f32) -> std::ops::Range<usize> {
+        let first = self.centers.partition_point(|&freq| freq < min_freq);
+        let last = self.centers.partition_point(|&freq| freq <= max_freq);
+
+        first..last.max(first)
+    }
+}
+
+/// Turns frames of samples into amplitude spectra
+#[derive(Debug)]
+pub enum Analyzer {
+    Fft {
+        window: WindowTable,
+        planner: FftPlanner,
+        fft_size: usize,
+        sample_rate: u32,
+    },
+    ConstantQ(ConstantQ),
+}
+
+impl Analyzer {
+    /// How many of the most recent samples make up a frame
+    pub fn frame_len(&self) -> usize {
+        match self {
+            Analyzer::Fft { fft_size, .. } => *fft_size,
+            Analyzer::ConstantQ(cqt) => cqt.frame_len(),
+        }
+    }
+
+    pub fn bins(&self) -> Bins {
+        match self {
+            Analyzer::Fft {
+                fft_size,
+                sample_rate,
+                ..
+            } => Bins::linear(fft_size / 2, *sample_rate as f32 / *fft_size as f32),
+            Analyzer::ConstantQ(cqt) => cqt.bins(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        match self {
+            Analyzer::Fft {
+                window, planner, ..
+            } => process(samples, window, planner),
+            Analyzer::ConstantQ(cqt) => cqt.process(samples),
+        }
+    }
+}
+
+/// Maps amplified amplitudes to numbers between 0 and 1
+#[derive(Debug, Clone, Copy)]
+pub struct Squash {
+    mapping: AmplitudeMapping,
+    // the normalized amplitude of a full-scale sine, i.e. 0 dBFS
+    full_scale: f32,
+}
+
+impl Squash {
+    pub fn new(mapping: AmplitudeMapping, fft_size: usize) -> Self {
+        Squash {
+            mapping,
+            full_scale: (fft_size as f32).sqrt() / 2.0,
+        }
+    }
+
+    /// Amplify the spectrum by k and map the amplitudes into [0, 1]
+    pub fn apply(&self, spectrum: &mut [f32], k: f32) {
+        for ampl in spectrum.iter_mut() {
+            let x = k * *ampl;
+            *ampl = match self.mapping {
+                // a sigmoid-type function (x / sqrt(1 + x^2))
+                AmplitudeMapping::Sigmoid => x / (1.0 + x * x).sqrt(),
+                // linear in decibels between the floor and the ceiling
+                AmplitudeMapping::Db {
+                    floor_db,
+                    ceiling_db,
+                } => {
+                    let db = 20.0 * (x / self.full_scale).max(f32::MIN_POSITIVE).log10();
+                    ((db - floor_db) / (ceiling_db - floor_db)).clamp(0.0, 1.0)
+                }
+            };
+        }
+    }
+
+    /// The amplified amplitude that gets mapped to the given level
+    pub fn inverse(&self, level: f32) -> f32 {
+        let level = level.clamp(0.01, 0.99);
+        match self.mapping {
+            AmplitudeMapping::Sigmoid => level / (1.0 - level * level).sqrt(),
+            AmplitudeMapping::Db {
+                floor_db,
+                ceiling_db,
+            } => self.full_scale * 10f32.powf((floor_db + level * (ceiling_db - floor_db)) / 20.0),
+        }
+    }
+}
+
+/// Noise floor of every frequency bin, measured while the input is silent and then subtracted from the spectrum
+#[derive(Debug)]
+pub struct NoiseFloor {
+    frames_left: usize,
+    n_frames: usize,
+    power: Vec<f32>,
+}
+
+impl NoiseFloor {
+    /// Return a noise floor that will be measured over the next n_frames frames
+    pub fn new(n_frames: usize) -> Self {
+        NoiseFloor {
+            frames_left: n_frames,
+            n_frames,
+            power: Vec::new(),
+        }
+    }
+
+    pub fn is_measuring(&self) -> bool {
+        self.frames_left > 0
+    }
+
+    /// Feed the next frame to the measurement, returning false if it's already finished
+    pub fn measure(&mut self, spectrum: &[f32]) -> bool {
+        if !self.is_measuring() {
+            return false;
+        }
+        if self.power.len() != spectrum.len() {
+            self.power = vec![0.0; spectrum.len()];
+        }
+        for (power, ampl) in self.power.iter_mut().zip(spectrum.iter()) {
+            *power += ampl * ampl / (self.n_frames as f32);
+        }
+        self.frames_left -= 1;
+
+        true
+    }
+
+    /// Subtract the (average) power of the noise from the spectrum
+    pub fn subtract(&self, spectrum: &mut [f32]) {
+        for (ampl, power) in spectrum.iter_mut().zip(self.power.iter()) {
+            *ampl = (*ampl * *ampl - power).max(0.0).sqrt();
+        }
+    }
+}
+
+/// Map a frequency (in Hz) onto the given scale, on which bands are equally wide
+fn to_scale(freq: f32, scale: BandScale) -> f32 {
+    match scale {
+        BandScale::Log => freq.ln(),
+        BandScale::Linear => freq,
+        BandScale::Mel => 2595.0 * (1.0 + freq / 700.0).log10(),
+        // Traunmüller's approximation
+        BandScale::Bark => 26.81 * freq / (1960.0 + freq) - 0.53,
+        BandScale::Erb => 21.4 * (1.0 + 0.00437 * freq).log10(),
+    }
+}
+
+fn from_scale(x: f32, scale: BandScale) -> f32 {
+    match scale {
+        BandScale::Log => x.exp(),
+        BandScale::Linear => x,
+        BandScale::Mel => 700.0 * (10f32.powf(x / 2595.0) - 1.0),
+        BandScale::Bark => 1960.0 * (x + 0.53) / (26.28 - x),
+        BandScale::Erb => (10f32.powf(x / 21.4) - 1.0) / 0.00437,
+    }
+}
+
+/// Split min_freq..max_freq into n_bands bands equally wide on the given scale, returning the n_bands + 1 edges
+pub fn band_edges(scale: BandScale, min_freq: u32, max_freq: u32, n_bands: usize) -> Vec<f32> {
+    let (lo, hi) = (
+        to_scale(min_freq as f32, scale),
+        to_scale(max_freq as f32, scale),
+    );
+    let mut edges = (0..=n_bands)
+        .map(|i| from_scale(lo + (hi - lo) * (i as f32) / (n_bands as f32), scale))
+        .collect::<Vec<_>>();
+    // so that rounding errors don't move the outermost edges
+    (edges[0], edges[n_bands]) = (min_freq as f32, max_freq as f32);
+
+    edges
+}
+
+/// Part of the spectrum of a channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Component {
+    Whole,
+    Harmonic,
+    Percussive,
+}
+
+/// Panels (given as indices into the list of colors) visualizing the spectrum of one channel
+#[derive(Debug)]
+pub struct PanelGroup {
+    pub panels: Vec<usize>,
+    pub channel: usize,
+    pub component: Component,
+    pub bands: Vec<BandBins>,
+}
+
+/// Elementwise maximum of the spectra of all channels
+pub fn loudest(spectra: &[Vec<f32>]) -> Vec<f32> {
+    let mut loudest = spectra[0].clone();
+    for spectrum in spectra.iter().skip(1) {
+        for (max, ampl) in loudest.iter_mut().zip(spectrum.iter()) {
+            *max = max.max(*ampl);
+        }
+    }
+
+    loudest
+}
+
+/// How the amplitude of a band is computed from the bins of a spectrum
+#[derive(Debug, Clone)]
+pub enum BandBins {
+    /// Maximum of the bins overlapping with the band, bins that stick out of it count only as much as they overlap
+    Overlapping(Vec<(usize, f32)>),
+    /// Linear interpolation between the two bins closest to the middle of a band that's too narrow to contain
+    /// the center of any bin
+    Interpolated { lower: usize, upper: usize, t: f32 },
+}
+
+impl BandBins {
+    pub fn amplitude(&self, spectrum: &[f32]) -> f32 {
+        match self {
+            BandBins::Overlapping(weights) => weights
+                .iter()
+                .map(|&(bin, weight)| spectrum[bin] * weight)
+                .fold(0.0, f32::max),
+            BandBins::Interpolated { lower, upper, t } => {
+                (1.0 - t) * spectrum[*lower] + t * spectrum[*upper]
+            }
+        }
+    }
+}
+
+/// Precompute how the amplitudes of the bands with the given edges are computed from the bins
+pub fn map_bands(band_edges: &[f32], bins: &Bins) -> Vec<BandBins> {
+    let n_bins = bins.centers.len();
+    band_edges
+        .windows(2)
+        .map(|edges| {
+            let (lo, hi) = (edges[0], edges[1]);
+            if n_bins == 0 {
+                return BandBins::Overlapping(Vec::new());
+            }
+            let first_center = bins.centers.partition_point(|&freq| freq < lo);
+            let last_center = bins.centers.partition_point(|&freq| freq < hi);
+            if first_center == last_center {
+                let mid = 0.5 * (lo + hi);
+                let upper = first_center.min(n_bins - 1);
+                let lower = first_center.saturating_sub(1);
+                let t = if upper > lower {
+                    ((mid - bins.centers[lower]) / (bins.centers[upper] - bins.centers[lower]))
+                        .clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                return BandBins::Interpolated { lower, upper, t };
+            }
+
+            let first_bin = bins
+                .edges
+                .partition_point(|&edge| edge <= lo)
+                .saturating_sub(1);
+            let last_bin = bins.edges.partition_point(|&edge| edge < hi).min(n_bins);
+            let weights = (first_bin..last_bin)
+                .filter_map(|i| {
+                    let (bin_lo, bin_hi) = (bins.edges[i], bins.edges[i + 1]);
+                    let overlap = (bin_hi.min(hi) - bin_lo.max(lo)).max(0.0);
+                    (overlap > 0.0)
+                        .then(|| (i, (overlap / (bin_hi - bin_lo).min(hi - lo)).min(1.0)))
+                })
+                .collect::<Vec<_>>();
+            BandBins::Overlapping(weights)
         })
         .collect::<Vec<_>>()
 }
 
 pub fn update_colors(
     colors: &mut [Hwb],
-    spectrum: Vec<f32>,
-    min_freq: u32,
-    max_freq: u32,
-    hz_per_bin: u32,
+    panels: &[usize],
+    spectrum: &[f32],
+    bands: &[BandBins],
+    envelopes: &mut Envelopes,
 ) {
+    for (&panel, band) in panels.iter().zip(bands.iter()) {
+        colors[panel].whiteness = 1.0 - envelopes.update(panel, band.amplitude(spectrum));
+    }
+}
+
+/// Return the colors to be shown while a flash of the given amount (between 0 and 1) is fading out,
+/// the bigger the amount the closer the colors are to their fully saturated versions
+pub fn flash(colors: &[Hwb], amount: f32) -> Vec<Hwb> {
+    colors
+        .iter()
+        .map(|color| Hwb::new(color.hue, color.whiteness * (1.0 - amount), color.blackness))
+        .collect::<Vec<_>>()
+}
+
+/// Set the hues of the panels to the palette shifted by rotation panels, keeping the amplitudes of the panels as they are
+pub fn set_hues(colors: &mut [Hwb], palette: &[f32], rotation: usize) {
     let n_panels = colors.len();
-    let (min_freq, max_freq) = (min_freq as f32, max_freq as f32);
-    let multiplier = (max_freq / min_freq).powf(1.0 / n_panels as f32);
-    let (mut intervals, mut cutoff) = (Vec::new(), min_freq);
-    for _ in 0..n_panels {
-        cutoff *= multiplier;
-        intervals.push(cutoff.round().min(max_freq) as u32);
-    }
-    let (n_bins, mut cur_interval, mut cur_max) = (spectrum.len(), 0, 0.0);
-    for (i, ampl) in spectrum.into_iter().enumerate() {
-        let cur_freq = (i as u32) * hz_per_bin + hz_per_bin / 2;
-        if cur_freq > intervals[cur_interval] || i == n_bins - 1 {
-            let cur_whiteness = colors[cur_interval].whiteness;
-            let rate_func = |x: f32| -> f32 { 0.75 * (0.5 * (PI * x)).sin() };
-            if cur_max > 1.0 - cur_whiteness {
-                // louder -> subtract whiteness
-                colors[cur_interval].whiteness =
-                    (cur_whiteness - rate_func(cur_max - 1.0 + cur_whiteness)).max(0.0);
-            } else {
-                // quieter -> add whiteness
-                colors[cur_interval].whiteness =
-                    (cur_whiteness + rate_func(1.0 - cur_whiteness - cur_max)).min(1.0);
+    for (i, color) in colors.iter_mut().enumerate() {
+        color.hue = palette[(i + n_panels - rotation % n_panels) % n_panels].into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOWS: [Window; 5] = [
+        Window::Rectangular,
+        Window::Hann,
+        Window::Hamming,
+        Window::BlackmanHarris,
+        Window::FlatTop,
+    ];
+    // bins at least this far from the sine count as leakage
+    const LEAKAGE_DISTANCE: f64 = 5.0;
+
+    /// Full-scale sine completing the given number of periods in n samples
+    fn sine(n: usize, periods: f64) -> Vec<f32> {
+        (0..n)
+            .map(|i| (2.0 * std::f64::consts::PI * periods * i as f64 / n as f64).sin() as f32)
+            .collect::<Vec<_>>()
+    }
+
+    /// Levels (in dB relative to the sqrt(n) / 2 of a full-scale sine) of the highest bin
+    /// and of the highest bin at least LEAKAGE_DISTANCE bins away from the sine
+    fn peak_and_leakage(window: Window, n: usize, bin: f64) -> (f32, f32) {
+        let spectrum = process(
+            &sine(n, bin),
+            &mut WindowTable::new(window),
+            &mut FftPlanner::new(),
+        );
+        let full_scale = (n as f32).sqrt() / 2.0;
+        let peak = spectrum.iter().copied().fold(0.0, f32::max);
+        let leakage = spectrum
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| (i as f64 - bin).abs() >= LEAKAGE_DISTANCE)
+            .map(|(_, &ampl)| ampl)
+            .fold(0.0, f32::max);
+        let db = |ampl: f32| 20.0 * (ampl / full_scale).max(f32::MIN_POSITIVE).log10();
+
+        (db(peak), db(leakage))
+    }
+
+    #[test]
+    fn on_bin_sine_keeps_full_amplitude() {
+        for window in WINDOWS {
+            for n in [256, 1024, 4096] {
+                let (peak, leakage) = peak_and_leakage(window, n, (n / 10) as f64);
+                assert!(
+                    peak.abs() < 0.01,
+                    "{:?}, n = {}: peak at {} dB",
+                    window,
+                    n,
+                    peak
+                );
+                assert!(
+                    leakage < -100.0,
+                    "{:?}, n = {}: leakage at {} dB",
+                    window,
+                    n,
+                    leakage
+                );
             }
-            cur_max = 0.0;
-            cur_interval += 1;
         }
-        if cur_freq > *intervals.last().unwrap() {
-            break;
+    }
+
+    #[test]
+    fn half_bin_sine_stays_within_scalloping_loss() {
+        for window in WINDOWS {
+            let max_loss = match window {
+                Window::Rectangular => 3.95,
+                Window::Hann => 1.45,
+                Window::Hamming => 1.8,
+                Window::BlackmanHarris => 0.85,
+                Window::FlatTop => 0.1,
+            };
+            let (peak, _) = peak_and_leakage(window, 1024, 100.5);
+            assert!(
+                peak <= 0.01 && peak > -max_loss,
+                "{:?}: peak at {} dB",
+                window,
+                peak
+            );
+        }
+    }
+
+    #[test]
+    fn half_bin_sine_leakage_is_bounded() {
+        let leakage = |window| peak_and_leakage(window, 1024, 100.5).1;
+        for window in WINDOWS {
+            let max_leakage = match window {
+                Window::Rectangular => -20.0,
+                Window::Hann => -50.0,
+                Window::Hamming => -40.0,
+                Window::BlackmanHarris | Window::FlatTop => -85.0,
+            };
+            assert!(
+                leakage(window) < max_leakage,
+                "{:?}: leakage at {} dB",
+                window,
+                leakage(window)
+            );
+        }
+        let rectangular = leakage(Window::Rectangular);
+        assert!(leakage(Window::Hann) < rectangular - 25.0);
+        assert!(leakage(Window::BlackmanHarris) < rectangular - 60.0);
+    }
+
+    /// Check that every one of the n_bands bands maps onto at least one existing bin
+    fn assert_valid_mapping(bands: &[BandBins], n_bands: usize, n_bins: usize, context: &str) {
+        assert_eq!(bands.len(), n_bands, "{}", context);
+        for (i, band) in bands.iter().enumerate() {
+            match band {
+                BandBins::Overlapping(weights) => {
+                    assert!(!weights.is_empty(), "{}: band {} is empty", context, i);
+                    for &(bin, weight) in weights {
+                        assert!(bin < n_bins, "{}: band {} uses bin {}", context, i, bin);
+                        assert!(weight > 0.0 && weight <= 1.0, "{}: band {}", context, i);
+                    }
+                }
+                &BandBins::Interpolated { lower, upper, t } => {
+                    assert!(lower <= upper && upper < n_bins, "{}: band {}", context, i);
+                    assert!((0.0..=1.0).contains(&t), "{}: band {}", context, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bands_map_onto_fft_bins() {
+        let scales = [
+            BandScale::Log,
+            BandScale::Linear,
+            BandScale::Mel,
+            BandScale::Bark,
+            BandScale::Erb,
+        ];
+        // the last range reaches above the Nyquist frequency of the lower sample rates
+        let freq_ranges = [(20, 20000), (40, 4000), (1000, 1100), (20, 40000)];
+        for n_bands in [1, 2, 3, 12, 64, 200] {
+            for sample_rate in [22050, 44100, 48000, 96000] {
+                for fft_size in [256, 1024, 4096, 16384] {
+                    let n_bins = fft_size / 2;
+                    let bins = Bins::linear(n_bins, sample_rate as f32 / fft_size as f32);
+                    for scale in scales {
+                        for (min_freq, max_freq) in freq_ranges {
+                            let edges = band_edges(scale, min_freq, max_freq, n_bands);
+                            let context = format!(
+                                "{} bands of {}-{} Hz on the {:?} scale, {} Hz, FFT of {}",
+                                n_bands, min_freq, max_freq, scale, sample_rate, fft_size
+                            );
+                            let bands = map_bands(&edges, &bins);
+                            assert_valid_mapping(&bands, n_bands, n_bins, &context);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bands_map_onto_geometric_bins() {
+        // like those of the constant-Q transform, 24 per octave from 32.7 Hz
+        let centers = (0..200)
+            .map(|i| 32.7 * 2f32.powf(i as f32 / 24.0))
+            .collect::<Vec<_>>();
+        let bins = Bins::geometric(centers);
+        for n_bands in [1, 2, 12, 64, 500] {
+            let edges = band_edges(BandScale::Log, 20, 20000, n_bands);
+            let bands = map_bands(&edges, &bins);
+            assert_valid_mapping(&bands, n_bands, 200, &format!("{} bands", n_bands));
+        }
+    }
+
+    #[test]
+    fn band_above_last_bin_uses_last_bin() {
+        let bins = Bins::linear(8, 100.0);
+        let bands = map_bands(&[900.0, 1000.0], &bins);
+        match bands[..] {
+            [BandBins::Interpolated { lower, upper, .. }] => assert_eq!((lower, upper), (7, 7)),
+            _ => panic!("{:?}", bands),
+        }
+        let spectrum = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5];
+        assert_eq!(bands[0].amplitude(&spectrum), 0.5);
+    }
+
+    #[test]
+    fn band_between_bin_centers_is_interpolated() {
+        let bins = Bins::linear(8, 100.0);
+        let bands = map_bands(&[320.0, 340.0], &bins);
+        match bands[..] {
+            [BandBins::Interpolated { lower, upper, t }] => {
+                assert_eq!((lower, upper), (3, 4));
+                assert!((t - 0.3).abs() < 1e-6);
+            }
+            _ => panic!("{:?}", bands),
         }
-        cur_max = cur_max.max(ampl);
     }
 }
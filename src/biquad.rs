@@ -0,0 +1,123 @@
+use crate::config::Filter;
+
+/// Second-order IIR filter
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    // state of the transposed direct form II
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    /// Return a filter with the given coefficients, normalized by a0
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b0: (b[0] / a[0]) as f32,
+            b1: (b[1] / a[0]) as f32,
+            b2: (b[2] / a[0]) as f32,
+            a1: (a[1] / a[0]) as f32,
+            a2: (a[2] / a[0]) as f32,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Return the filter described in the config, with coefficients from the Audio EQ Cookbook
+    /// (see https://www.w3.org/TR/audio-eq-cookbook/)
+    pub fn from_config(filter: Filter, sample_rate: u32) -> Self {
+        let (freq, q, gain_db) = match filter {
+            Filter::HighPass { freq, q }
+            | Filter::LowPass { freq, q }
+            | Filter::BandPass { freq, q } => (freq, q, 0.0),
+            Filter::Peaking { freq, q, gain_db }
+            | Filter::LowShelf { freq, q, gain_db }
+            | Filter::HighShelf { freq, q, gain_db } => (freq, q, gain_db),
+        };
+        let w0 = 2.0 * std::f64::consts::PI * freq as f64 / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q as f64));
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+        let (b, a) = match filter {
+            Filter::HighPass { .. } => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            Filter::LowPass { .. } => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            // with a peak gain of 0 dB
+            Filter::BandPass { .. } => {
+                ([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+            }
+            Filter::Peaking { .. } => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            Filter::LowShelf { .. } => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+                ],
+            ),
+            Filter::HighShelf { .. } => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+                ],
+            ),
+        };
+
+        Biquad::new(b, a)
+    }
+
+    /// Filter the next sample
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+
+        y
+    }
+}
+
+/// Filters applied one after another
+#[derive(Debug, Clone)]
+pub struct FilterChain {
+    filters: Vec<Biquad>,
+}
+
+impl FilterChain {
+    pub fn new(filters: &[Filter], sample_rate: u32) -> Self {
+        FilterChain {
+            filters: filters
+                .iter()
+                .map(|&filter| Biquad::from_config(filter, sample_rate))
+                .collect::<Vec<_>>(),
+        }
+    }
+
+    /// Filter the next sample
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(x, |y, filter| filter.process(y))
+    }
+}
@@ -0,0 +1,77 @@
+use crate::audio::Bins;
+use crate::envelope::Envelopes;
+use palette::Hwb;
+use std::ops::Range;
+
+pub const N_PITCH_CLASSES: usize = 12;
+// MIDI note number of A4, the note that the tuning reference refers to
+const A4_NOTE: f32 = 69.0;
+
+/// Assignment of the bins of a spectrum to the 12 pitch classes (0 being C), with all octaves folded onto each other
+#[derive(Debug)]
+pub struct ChromaMap {
+    bins: Vec<(usize, usize)>,
+}
+
+impl ChromaMap {
+    /// Return the assignment of bins from min_freq to max_freq, with A4 tuned to tuning_hz
+    pub fn new(bins: &Bins, tuning_hz: f32, min_freq: u32, max_freq: u32) -> Self {
+        let semitone = 2f32.powf(1.0 / 12.0) - 1.0;
+        let bins = bins
+            .range(min_freq as f32, max_freq as f32)
+            .filter_map(|i| {
+                let freq = bins.centers[i];
+                // bins wider than a semitone can't tell notes apart
+                if bins.edges[i + 1] - bins.edges[i] > semitone * freq {
+                    return None;
+                }
+                let note = A4_NOTE + 12.0 * (freq / tuning_hz).log2();
+                Some((
+                    i,
+                    (note.round() as i64).rem_euclid(N_PITCH_CLASSES as i64) as usize,
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        ChromaMap { bins }
+    }
+
+    /// Fold the spectrum into the strength of every pitch class
+    pub fn chromagram(&self, spectrum: &[f32]) -> [f32; N_PITCH_CLASSES] {
+        let mut chroma = [0.0; N_PITCH_CLASSES];
+        for &(bin, pitch_class) in self.bins.iter() {
+            chroma[pitch_class] = f32::max(chroma[pitch_class], spectrum[bin]);
+        }
+
+        chroma
+    }
+}
+
+/// Pitch classes visualized by the panel numbered i (from 0) out of n_panels,
+/// with fewer than 12 panels some of them get multiple pitch classes, with more some pitch classes get multiple panels
+pub fn panel_pitch_classes(i: usize, n_panels: usize) -> Range<usize> {
+    let first = i * N_PITCH_CLASSES / n_panels;
+    let last = ((i + 1) * N_PITCH_CLASSES / n_panels).max(first + 1);
+
+    first..last
+}
+
+/// Hue of the pitch class, going around the color wheel from C to B
+pub fn hue(pitch_class: usize) -> f32 {
+    (pitch_class * 360 / N_PITCH_CLASSES) as f32
+}
+
+pub fn update_colors(
+    colors: &mut [Hwb],
+    chroma: &[f32; N_PITCH_CLASSES],
+    envelopes: &mut Envelopes,
+) {
+    let n_panels = colors.len();
+    for (i, color) in colors.iter_mut().enumerate() {
+        let cur_max = chroma[panel_pitch_classes(i, n_panels)]
+            .iter()
+            .copied()
+            .fold(0.0, f32::max);
+        color.whiteness = 1.0 - envelopes.update(i, cur_max);
+    }
+}
@@ -17,6 +17,357 @@ pub enum Sort {
     Desc,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Bands,
+    Chroma,
+    Vu,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Downmix {
+    #[default]
+    Mean,
+    Rms,
+    Mid,
+    Side,
+    Channel(usize),
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Window {
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Analysis {
+    #[default]
+    Fft,
+    Cqt,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CqtConfig {
+    pub bins_per_octave: u32,
+}
+
+impl Default for CqtConfig {
+    fn default() -> Self {
+        CqtConfig {
+            bins_per_octave: 24,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum AmplitudeMapping {
+    #[default]
+    Sigmoid,
+    Db {
+        floor_db: f32,
+        ceiling_db: f32,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Filter {
+    HighPass {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    LowPass {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    BandPass {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+    },
+    Peaking {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+        gain_db: f32,
+    },
+    LowShelf {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+        gain_db: f32,
+    },
+    HighShelf {
+        freq: f32,
+        #[serde(default = "default_q")]
+        q: f32,
+        gain_db: f32,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EqPreset {
+    #[default]
+    None,
+    A,
+    C,
+    Pink,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct EqConfig {
+    pub preset: EqPreset,
+    pub points: Vec<(f32, f32)>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AgcConfig {
+    pub enabled: bool,
+    pub attack_ms: u32,
+    pub release_ms: u32,
+    pub target_level: f32,
+    pub max_gain: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            enabled: false,
+            attack_ms: 50,
+            release_ms: 3000,
+            target_level: 0.9,
+            max_gain: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct EnvelopeConfig {
+    pub attack_ms: u32,
+    pub release_ms: u32,
+    pub hold_ms: u32,
+    pub hysteresis: f32,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        EnvelopeConfig {
+            attack_ms: 20,
+            release_ms: 150,
+            hold_ms: 0,
+            hysteresis: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct OnsetConfig {
+    pub enabled: bool,
+    pub sensitivity: f32,
+    pub refractory_ms: u32,
+    pub flash_ms: u32,
+}
+
+impl Default for OnsetConfig {
+    fn default() -> Self {
+        OnsetConfig {
+            enabled: false,
+            sensitivity: 0.5,
+            refractory_ms: 100,
+            flash_ms: 150,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BandScale {
+    #[default]
+    Log,
+    Linear,
+    Mel,
+    Bark,
+    Erb,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteStep {
+    None,
+    Beat,
+    Bar,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TempoConfig {
+    pub palette_step: PaletteStep,
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        TempoConfig {
+            palette_step: PaletteStep::None,
+            min_bpm: 60.0,
+            max_bpm: 180.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ChromaConfig {
+    pub tuning_hz: f32,
+}
+
+impl Default for ChromaConfig {
+    fn default() -> Self {
+        ChromaConfig { tuning_hz: 440.0 }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct KeyConfig {
+    pub enabled: bool,
+    pub smoothing_secs: f32,
+    pub crossfade_secs: f32,
+    pub hue_span: f32,
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        KeyConfig {
+            enabled: false,
+            smoothing_secs: 30.0,
+            crossfade_secs: 5.0,
+            hue_span: 120.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct HpssConfig {
+    pub enabled: bool,
+    pub history_ms: u32,
+    pub kernel_bins: usize,
+    pub harmonic_panels: Vec<usize>,
+    pub percussive_panels: Vec<usize>,
+}
+
+impl Default for HpssConfig {
+    fn default() -> Self {
+        HpssConfig {
+            enabled: false,
+            history_ms: 200,
+            kernel_bins: 17,
+            harmonic_panels: Vec::new(),
+            percussive_panels: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoudnessWindow {
+    Momentary,
+    ShortTerm,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LoudnessConfig {
+    pub enabled: bool,
+    pub brightness: bool,
+    pub window: LoudnessWindow,
+    pub floor_lufs: f32,
+    pub ceiling_lufs: f32,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        LoudnessConfig {
+            enabled: false,
+            brightness: false,
+            window: LoudnessWindow::Momentary,
+            floor_lufs: -40.0,
+            ceiling_lufs: -10.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StereoConfig {
+    pub enabled: bool,
+    pub split_axis: Axis,
+    /// Coordinate below which panels visualize the left channel, the midpoint of the active panels if None
+    /// (rather than 0, since the coordinates of real Nanoleaf layouts are never negative)
+    pub split_at: Option<i16>,
+}
+
+impl Default for StereoConfig {
+    fn default() -> Self {
+        StereoConfig {
+            enabled: false,
+            split_axis: Axis::X,
+            split_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Descriptor {
+    Centroid,
+    Flatness,
+    Rolloff,
+    Rms,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DescriptorTarget {
+    Hue,
+    Saturation,
+    Brightness,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DescriptorMapping {
+    pub descriptor: Descriptor,
+    pub target: DescriptorTarget,
+    pub amount: f32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MpdFifoConfig {
+    pub path: PathBuf,
+    pub format: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct NlConfig {
     pub primary_axis: Axis,
@@ -32,11 +383,69 @@ pub struct NlConfig {
 pub struct Config {
     pub nl_config: NlConfig,
     pub audio_device: String,
+    #[serde(default)]
+    pub mpd_fifo: Option<MpdFifoConfig>,
+    #[serde(default)]
+    pub downmix: Downmix,
+    #[serde(default)]
+    pub mode: Mode,
     pub min_freq: u32,
     pub max_freq: u32,
+    #[serde(default)]
+    pub band_scale: BandScale,
     pub default_gain: f32,
+    #[serde(default)]
+    pub agc: AgcConfig,
+    #[serde(default)]
+    pub amplitude_mapping: AmplitudeMapping,
+    #[serde(default)]
+    pub noise_floor_secs: f32,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub eq: EqConfig,
+    #[serde(default)]
+    pub envelope: EnvelopeConfig,
     pub transition_time: u16,
     pub hues: Vec<u16>,
+    #[serde(default)]
+    pub analysis: Analysis,
+    #[serde(default)]
+    pub cqt: CqtConfig,
+    #[serde(default)]
+    pub window: Window,
+    #[serde(default = "default_fft_size")]
+    pub fft_size: usize,
+    #[serde(default = "default_hop_size")]
+    pub hop_size: usize,
+    #[serde(default)]
+    pub onset: OnsetConfig,
+    #[serde(default)]
+    pub tempo: TempoConfig,
+    #[serde(default)]
+    pub stereo: StereoConfig,
+    #[serde(default)]
+    pub hpss: HpssConfig,
+    #[serde(default)]
+    pub loudness: LoudnessConfig,
+    #[serde(default)]
+    pub chroma: ChromaConfig,
+    #[serde(default)]
+    pub key: KeyConfig,
+    #[serde(default)]
+    pub descriptors: Vec<DescriptorMapping>,
+}
+
+pub fn default_q() -> f32 {
+    std::f32::consts::FRAC_1_SQRT_2
+}
+
+pub fn default_fft_size() -> usize {
+    4096
+}
+
+pub fn default_hop_size() -> usize {
+    2048
 }
 
 pub fn try_read_from_file(config_file_path: &Path) -> Result<Option<Config>, anyhow::Error> {
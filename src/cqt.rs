@@ -0,0 +1,261 @@
+use crate::audio::{self, Bins};
+use crate::config::Window;
+use num::complex::Complex;
+
+// the decimation filter has 2 * DECIMATION_HALF_LEN + 1 taps
+const DECIMATION_HALF_LEN: usize = 15;
+// fraction of the decimated sample rate below which the signal gets through the decimation without aliasing,
+// a bit below the limit given in decimation_filter
+const ALIAS_FREE_FREQ: f32 = 0.3;
+
+/// Constant-Q transform, with log-spaced bins that are equally wide on a log scale (i.e. the same number of bins
+/// per octave), analyzing each bin over a window that gets longer the lower its frequency
+///
+/// Computed on multiple rates: the samples are repeatedly low-passed and decimated by 2, and each bin is analyzed
+/// at the lowest rate that still fits it, so that even the windows of the lowest bins are only a few hundred
+/// samples long
+#[derive(Debug)]
+pub struct ConstantQ {
+    centers: Vec<f32>,
+    // windowed complex sinusoids, one per bin, at sample_rate / 2^level
+    kernels: Vec<(usize, Vec<Complex<f32>>)>,
+    filter: Vec<f32>,
+    frame_len: usize,
+    // the samples at each lower rate and the even and odd samples of the rate above it, reused between frames
+    decimated: Vec<Vec<f32>>,
+    phases: [Vec<f32>; 2],
+}
+
+/// Low-pass (Blackman-windowed sinc) filter that halves the bandwidth before decimating by 2
+///
+/// Its transition band spans about 5.5 / 31 of the sample rate around the new Nyquist frequency, so what's left
+/// after decimating is free of aliases (and of the filter's roll-off) below about 0.32 of the new sample rate
+fn decimation_filter() -> Vec<f32> {
+    let m = DECIMATION_HALF_LEN as i64;
+    let taps = (-m..=m)
+        .map(|k| {
+            // a half-band filter, every other tap is zero
+            let sinc = if k == 0 {
+                1.0
+            } else if k % 2 == 0 {
+                0.0
+            } else {
+                let x = std::f64::consts::FRAC_PI_2 * k as f64;
+                x.sin() / x
+            };
+            let phase = std::f64::consts::PI * (k + m) as f64 / m as f64;
+            let blackman = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            sinc * blackman
+        })
+        .collect::<Vec<_>>();
+    let sum = taps.iter().sum::<f64>();
+
+    taps.iter()
+        .map(|tap| (tap / sum) as f32)
+        .collect::<Vec<_>>()
+}
+
+impl ConstantQ {
+    /// Return a transform with bins_per_octave bins per octave from min_freq to max_freq,
+    /// normalized so that the amplitudes match those computed by an FFT of size fft_size
+    pub fn new(
+        sample_rate: u32,
+        min_freq: u32,
+        max_freq: u32,
+        bins_per_octave: u32,
+        window: Window,
+        fft_size: usize,
+    ) -> Self {
+        let bins_per_octave = bins_per_octave.max(1) as f32;
+        // ratio of a bin's frequency to its bandwidth, the same for all bins
+        let q = 1.0 / (2f32.powf(1.0 / bins_per_octave) - 1.0);
+        let n_bins =
+            (bins_per_octave * (max_freq as f32 / min_freq as f32).log2()).floor() as usize + 1;
+        let centers = (0..n_bins)
+            .map(|k| min_freq as f32 * 2f32.powf(k as f32 / bins_per_octave))
+            .collect::<Vec<_>>();
+        let kernels = centers
+            .iter()
+            .map(|&freq| {
+                // the upper edge of the bin must stay clear of the aliasing at its rate
+                let upper_freq = freq * 2f32.powf(0.5 / bins_per_octave);
+                let level = (ALIAS_FREE_FREQ * sample_rate as f32 / upper_freq)
+                    .log2()
+                    .floor()
+                    .max(0.0) as usize;
+                let rate = sample_rate as f32 / (1 << level) as f32;
+                let len = ((q * rate / freq).ceil() as usize).max(1);
+                let window = audio::window_coefficients(window, len);
+                // a full-scale sine in the middle of the bin ends up with the same amplitude as in an FFT
+                let norm = (fft_size as f32).sqrt() / window.iter().sum::<f32>();
+                let kernel = window
+                    .iter()
+                    .enumerate()
+                    .map(|(n, w)| {
+                        let phase = -2.0 * std::f32::consts::PI * freq * (n as f32) / rate;
+                        Complex::from_polar(w * norm, phase)
+                    })
+                    .collect::<Vec<_>>();
+
+                (level, kernel)
+            })
+            .collect::<Vec<_>>();
+        let n_levels = kernels
+            .iter()
+            .map(|&(level, _)| level + 1)
+            .max()
+            .unwrap_or(1);
+        // going up from the lowest rate, each level needs its longest window and enough samples
+        // to decimate into those needed by the level below it
+        let frame_len = (0..n_levels).rev().fold(0, |needed_below, level| {
+            let longest_window = kernels
+                .iter()
+                .filter(|&&(kernel_level, _)| kernel_level == level)
+                .map(|(_, kernel)| kernel.len())
+                .max()
+                .unwrap_or(0);
+            let for_decimation = match needed_below {
+                0 => 0,
+                _ => 2 * needed_below + 2 * DECIMATION_HALF_LEN - 1,
+            };
+            longest_window.max(for_decimation)
+        });
+
+        ConstantQ {
+            centers,
+            kernels,
+            filter: decimation_filter(),
+            frame_len,
+            decimated: vec![Vec::new(); n_levels - 1],
+            phases: [Vec::new(), Vec::new()],
+        }
+    }
+
+    /// How many of the most recent samples are needed, i.e. the length of the longest window
+    /// (at the full rate) plus the length of the decimation filters
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    pub fn bins(&self) -> Bins {
+        Bins::geometric(self.centers.clone())
+    }
+
+    /// Low-pass the samples and keep every other one, each output sample is centered on an input one,
+    /// the last of them DECIMATION_HALF_LEN samples before the newest
+    fn decimate(
+        filter: &[f32],
+        samples: &[f32],
+        phases: &mut [Vec<f32>; 2],
+        decimated: &mut Vec<f32>,
+    ) {
+        decimated.clear();
+        if samples.len() < filter.len() {
+            return;
+        }
+        let first = (samples.len() - filter.len()) % 2;
+        decimated.resize((samples.len() - first - filter.len()) / 2 + 1, 0.0);
+        // split into the even and odd samples, so that each tap is applied to a contiguous slice
+        for (phase, phase_samples) in phases.iter_mut().enumerate() {
+            phase_samples.clear();
+            phase_samples.extend(samples[(first + phase)..].iter().step_by(2));
+        }
+        for (k, &tap) in filter.iter().enumerate() {
+            if tap == 0.0 {
+                continue;
+            }
+            for (y, x) in decimated.iter_mut().zip(phases[k % 2][(k / 2)..].iter()) {
+                *y += tap * x;
+            }
+        }
+    }
+
+    /// Compute the amplitude spectrum of the samples (frame_len of them, from the oldest to the newest),
+    /// the windows of all bins end at the newest sample, delayed by the decimation filters at the lower rates
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        for level in 0..self.decimated.len() {
+            let (higher, lower) = self.decimated.split_at_mut(level);
+            let higher = higher.last().map_or(samples, |higher| &higher[..]);
+            Self::decimate(&self.filter, higher, &mut self.phases, &mut lower[0]);
+        }
+        self.kernels
+            .iter()
+            .map(|&(level, ref kernel)| {
+                let samples = if level == 0 {
+                    samples
+                } else {
+                    &self.decimated[level - 1]
+                };
+                let start = samples.len().saturating_sub(kernel.len());
+                samples[start..]
+                    .iter()
+                    .zip(kernel.iter())
+                    .map(|(x, c)| c * x)
+                    .sum::<Complex<f32>>()
+                    .norm()
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 8000;
+    const FFT_SIZE: usize = 1024;
+
+    /// Full-scale sine at the frequency, frame_len samples of it
+    fn sine(cqt: &ConstantQ, freq: f32) -> Vec<f32> {
+        (0..cqt.frame_len())
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * freq as f64 * i as f64;
+                (phase / SAMPLE_RATE as f64).sin() as f32
+            })
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn sine_peaks_at_its_bin() {
+        for window in [
+            Window::Rectangular,
+            Window::Hann,
+            Window::Hamming,
+            Window::BlackmanHarris,
+            Window::FlatTop,
+        ] {
+            // from 100 Hz, analyzed at 500 Hz, to 3 kHz, analyzed at the full rate
+            let mut cqt = ConstantQ::new(SAMPLE_RATE, 100, 3000, 12, window, FFT_SIZE);
+            let mut levels = Vec::new();
+            for bin in (0..cqt.centers.len()).step_by(5) {
+                let spectrum = cqt.process(&sine(&cqt, cqt.centers[bin]));
+                let peak = (0..spectrum.len())
+                    .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+                    .unwrap();
+                let db = 20.0 * (spectrum[peak] / ((FFT_SIZE as f32).sqrt() / 2.0)).log10();
+                assert_eq!(peak, bin, "{:?}: {:?}", window, spectrum);
+                assert!(
+                    db.abs() < 0.1,
+                    "{:?}, bin {}: peak at {} dB",
+                    window,
+                    bin,
+                    db
+                );
+                levels.push(cqt.kernels[bin].0);
+            }
+            // all the rates, from the full one to the lowest one, were tested
+            levels.dedup();
+            assert_eq!(levels, [4, 3, 2, 1, 0]);
+        }
+    }
+
+    #[test]
+    fn decimated_octaves_reject_aliases() {
+        let mut cqt = ConstantQ::new(SAMPLE_RATE, 100, 3000, 12, Window::Hann, FFT_SIZE);
+        // 100 Hz is analyzed at 500 Hz, where 400 Hz would alias onto it without the low-pass filters
+        assert_eq!(cqt.kernels[0].0, 4);
+        let spectrum = cqt.process(&sine(&cqt, 400.0));
+        let db = 20.0 * (spectrum[0] / ((FFT_SIZE as f32).sqrt() / 2.0)).log10();
+        assert!(db < -60.0, "alias at {} dB", db);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::audio::Bins;
+use crate::config::{Descriptor, DescriptorMapping, DescriptorTarget};
+use crate::envelope::smoothing_coeff;
+use palette::Hwb;
+
+// fraction of the spectrum's power below the rolloff frequency
+const ROLLOFF_POWER: f32 = 0.85;
+// time constant (in seconds) of the smoothing applied to the descriptors, so that colors don't flicker
+const SMOOTHING_TIME: f32 = 0.2;
+
+/// Global descriptors of the spectrum, each of them normalized to [0, 1]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Descriptors {
+    /// Center of mass of the spectrum, on a log scale from min_freq (0) to max_freq (1)
+    pub centroid: f32,
+    /// How noise-like (1) rather than tonal (0) the spectrum is
+    pub flatness: f32,
+    /// Frequency below which most of the power lies, on the same scale as the centroid
+    pub rolloff: f32,
+    /// Root mean square of the amplitudes
+    pub rms: f32,
+}
+
+impl Descriptors {
+    /// Compute the descriptors of the spectrum (with amplitudes between 0 and 1) in the given frequency range
+    pub fn new(spectrum: &[f32], bins: &Bins, min_freq: u32, max_freq: u32) -> Self {
+        let range = bins.range(min_freq as f32, max_freq as f32);
+        let centers = &bins.centers[range.clone()];
+        let bins = &spectrum[range];
+        if bins.is_empty() {
+            return Descriptors::default();
+        }
+        let n = bins.len() as f32;
+        let total_power = bins.iter().map(|a| a * a).sum::<f32>();
+        let total_ampl = bins.iter().sum::<f32>();
+        if total_power <= f32::EPSILON {
+            return Descriptors::default();
+        }
+
+        let (log_min, log_max) = ((min_freq as f32).ln(), (max_freq as f32).ln());
+        let normalize_freq = |freq: f32| {
+            ((freq.max(min_freq as f32).ln() - log_min) / (log_max - log_min)).clamp(0.0, 1.0)
+        };
+
+        let centroid = bins
+            .iter()
+            .enumerate()
+            .map(|(i, a)| centers[i] * a)
+            .sum::<f32>()
+            / total_ampl;
+        // geometric mean over arithmetic mean of the power spectrum
+        let log_mean = bins
+            .iter()
+            .map(|a| (a * a).max(f32::MIN_POSITIVE).ln())
+            .sum::<f32>()
+            / n;
+        let flatness = log_mean.exp() / (total_power / n);
+        let mut cumulative_power = 0.0;
+        let rolloff_bin = bins
+            .iter()
+            .position(|a| {
+                cumulative_power += a * a;
+                cumulative_power >= ROLLOFF_POWER * total_power
+            })
+            .unwrap_or(bins.len() - 1);
+
+        Descriptors {
+            centroid: normalize_freq(centroid),
+            flatness: flatness.clamp(0.0, 1.0),
+            rolloff: normalize_freq(centers[rolloff_bin]),
+            rms: (total_power / n).sqrt(),
+        }
+    }
+
+    pub fn get(&self, descriptor: Descriptor) -> f32 {
+        match descriptor {
+            Descriptor::Centroid => self.centroid,
+            Descriptor::Flatness => self.flatness,
+            Descriptor::Rolloff => self.rolloff,
+            Descriptor::Rms => self.rms,
+        }
+    }
+}
+
+/// Exponential smoothing of the descriptors over consecutive frames
+#[derive(Debug)]
+pub struct DescriptorSmoother {
+    coeff: f32,
+    smoothed: Option<Descriptors>,
+}
+
+impl DescriptorSmoother {
+    pub fn new(frame_duration: f32) -> Self {
+        DescriptorSmoother {
+            coeff: smoothing_coeff(frame_duration, SMOOTHING_TIME),
+            smoothed: None,
+        }
+    }
+
+    pub fn update(&mut self, descriptors: Descriptors) -> Descriptors {
+        let smoothed = match self.smoothed {
+            Some(prev) => {
+                let smooth = |prev: f32, cur: f32| prev + self.coeff * (cur - prev);
+                Descriptors {
+                    centroid: smooth(prev.centroid, descriptors.centroid),
+                    flatness: smooth(prev.flatness, descriptors.flatness),
+                    rolloff: smooth(prev.rolloff, descriptors.rolloff),
+                    rms: smooth(prev.rms, descriptors.rms),
+                }
+            }
+            None => descriptors,
+        };
+        self.smoothed = Some(smoothed);
+
+        smoothed
+    }
+}
+
+/// Modify the colors according to the descriptors and the mappings between them
+pub fn apply(colors: &mut [Hwb], mappings: &[DescriptorMapping], descriptors: &Descriptors) {
+    for mapping in mappings.iter() {
+        let value = descriptors.get(mapping.descriptor);
+        for color in colors.iter_mut() {
+            match mapping.target {
+                DescriptorTarget::Hue => color.hue += mapping.amount * value,
+                // towards white (less saturated)
+                DescriptorTarget::Saturation => {
+                    color.whiteness +=
+                        (1.0 - color.whiteness) * (mapping.amount * value).clamp(0.0, 1.0)
+                }
+                // towards black when the value is low
+                DescriptorTarget::Brightness => {
+                    color.blackness = color
+                        .blackness
+                        .max((mapping.amount * (1.0 - value)).clamp(0.0, 1.0))
+                }
+            }
+        }
+    }
+}
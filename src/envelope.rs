@@ -0,0 +1,121 @@
+/// Fraction of the distance to its target that a one-pole smoother with the time constant (in seconds)
+/// covers in one frame of frame_duration seconds, a time of 0 meaning no smoothing
+pub fn smoothing_coeff(frame_duration: f32, time: f32) -> f32 {
+    1.0 - (-frame_duration / time.max(f32::EPSILON)).exp()
+}
+
+/// State of the envelope of a single band
+#[derive(Debug, Clone, Copy, Default)]
+struct Band {
+    level: f32,
+    target: f32,
+    hold_left: f32,
+}
+
+/// Attack/release envelope followers of many bands, defined in seconds so that they don't depend on the frame rate
+#[derive(Debug)]
+pub struct Envelopes {
+    frame_duration: f32,
+    attack: f32,
+    release: f32,
+    hold_time: f32,
+    hysteresis: f32,
+    bands: Vec<Band>,
+}
+
+impl Envelopes {
+    /// Return envelopes of n_bands bands for frames analyzed every frame_duration seconds, with attack, release
+    /// and peak hold times in seconds, changes of the amplitude smaller than hysteresis are ignored
+    pub fn new(
+        n_bands: usize,
+        frame_duration: f32,
+        attack_time: f32,
+        release_time: f32,
+        hold_time: f32,
+        hysteresis: f32,
+    ) -> Self {
+        Envelopes {
+            frame_duration,
+            attack: smoothing_coeff(frame_duration, attack_time),
+            release: smoothing_coeff(frame_duration, release_time),
+            hold_time,
+            hysteresis,
+            bands: vec![Band::default(); n_bands],
+        }
+    }
+
+    /// Feed the amplitude (between 0 and 1) of the band in the next frame, returning the band's level
+    pub fn update(&mut self, band: usize, ampl: f32) -> f32 {
+        let band = &mut self.bands[band];
+        if (ampl - band.target).abs() > self.hysteresis {
+            band.target = ampl;
+        }
+        if band.target >= band.level {
+            band.level += self.attack * (band.target - band.level);
+            band.hold_left = self.hold_time;
+        } else if band.hold_left > 0.0 {
+            band.hold_left -= self.frame_duration;
+        } else {
+            band.level += self.release * (band.target - band.level);
+        }
+
+        band.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+    const ATTACK_TIME: f32 = 0.05;
+    const RELEASE_TIME: f32 = 0.2;
+    // a bit less than 2 frames of 2048 samples, so that both hop sizes hold for the same number of samples
+    const HOLD_TIME: f32 = 0.09;
+
+    /// Levels of an envelope fed a step up for 4 frames of 2048 samples and then a step down
+    /// for another 8 of them, taken at the end of both steps
+    fn step_response(hop_size: usize) -> (f32, f32) {
+        let frame_duration = hop_size as f32 / SAMPLE_RATE;
+        let frames = |n_samples: usize| n_samples / hop_size;
+        let mut envelopes =
+            Envelopes::new(1, frame_duration, ATTACK_TIME, RELEASE_TIME, HOLD_TIME, 0.0);
+        let mut level = 0.0;
+        for _ in 0..frames(4 * 2048) {
+            level = envelopes.update(0, 1.0);
+        }
+        let after_attack = level;
+        for _ in 0..frames(8 * 2048) {
+            level = envelopes.update(0, 0.0);
+        }
+
+        (after_attack, level)
+    }
+
+    #[test]
+    fn step_response_does_not_depend_on_frame_rate() {
+        let (attack_fine, release_fine) = step_response(512);
+        let (attack_coarse, release_coarse) = step_response(2048);
+        // 4 * 2048 samples are about 3.7 attack times
+        let expected_attack = 1.0 - (-(4.0 * 2048.0 / SAMPLE_RATE) / ATTACK_TIME).exp();
+        assert!(
+            (attack_fine - expected_attack).abs() < 1e-3,
+            "{}",
+            attack_fine
+        );
+        assert!(
+            (attack_fine - attack_coarse).abs() < 1e-3,
+            "{} != {}",
+            attack_fine,
+            attack_coarse
+        );
+        // the level fell for the same time after the same hold
+        assert!(release_fine < 0.5 * attack_fine, "{}", release_fine);
+        assert!(
+            (release_fine - release_coarse).abs() < 1e-3,
+            "{} != {}",
+            release_fine,
+            release_coarse
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::audio::Bins;
+use crate::config::EqPreset;
+
+/// A-weighting (IEC 61672) of the frequency, in dB
+fn a_weighting(freq: f32) -> f32 {
+    let f2 = freq * freq;
+    let r = 12194f32.powi(2) * f2 * f2
+        / ((f2 + 20.6f32.powi(2))
+            * ((f2 + 107.7f32.powi(2)) * (f2 + 737.9f32.powi(2))).sqrt()
+            * (f2 + 12194f32.powi(2)));
+
+    20.0 * r.log10() + 2.0
+}
+
+/// C-weighting (IEC 61672) of the frequency, in dB
+fn c_weighting(freq: f32) -> f32 {
+    let f2 = freq * freq;
+    let r = 12194f32.powi(2) * f2 / ((f2 + 20.6f32.powi(2)) * (f2 + 12194f32.powi(2)));
+
+    20.0 * r.log10() + 0.06
+}
+
+/// Gain (in dB) of the preset at the frequency
+fn preset_gain(preset: EqPreset, freq: f32) -> f32 {
+    match preset {
+        EqPreset::None => 0.0,
+        EqPreset::A => a_weighting(freq),
+        EqPreset::C => c_weighting(freq),
+        // rising by 3 dB per octave, so that pink noise is flat
+        EqPreset::Pink => 3.0 * (freq / 1000.0).log2(),
+    }
+}
+
+/// Gain (in dB) at the frequency, interpolated linearly on a log-frequency scale between the (Hz, dB) points
+/// and constant beyond the outermost ones
+fn interpolate(points: &[(f32, f32)], freq: f32) -> f32 {
+    let next = points.partition_point(|&(point_freq, _)| point_freq < freq);
+    if points.is_empty() {
+        0.0
+    } else if next == 0 {
+        points[0].1
+    } else if next == points.len() {
+        points[next - 1].1
+    } else {
+        let ((f0, g0), (f1, g1)) = (points[next - 1], points[next]);
+        let t = (freq / f0).ln() / (f1 / f0).ln();
+        g0 + t * (g1 - g0)
+    }
+}
+
+/// Amplitude gains of all bins, the preset combined with the user-defined (Hz, dB) points
+pub fn gains(bins: &Bins, preset: EqPreset, points: &[(f32, f32)]) -> Vec<f32> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    bins.centers
+        .iter()
+        .map(|&freq| {
+            // so that the DC bin doesn't get an infinite attenuation
+            let freq = freq.max(1.0);
+            let gain_db = preset_gain(preset, freq) + interpolate(&points, freq);
+            10f32.powf(gain_db / 20.0)
+        })
+        .collect::<Vec<_>>()
+}
+
+/// Apply the gains to the (not yet squashed) spectrum
+pub fn apply(spectrum: &mut [f32], gains: &[f32]) {
+    for (ampl, gain) in spectrum.iter_mut().zip(gains.iter()) {
+        *ampl *= gain;
+    }
+}
@@ -0,0 +1,165 @@
+use num::complex::Complex;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Precomputed tables and scratch space for a real-to-complex FFT of a fixed size
+#[derive(Debug)]
+pub struct RealFft {
+    n: usize,
+    bit_reversed: Vec<usize>,
+    twiddles: Vec<Complex<f32>>,
+    split_twiddles: Vec<Complex<f32>>,
+    buf: Vec<Complex<f32>>,
+    output: Vec<Complex<f32>>,
+}
+
+/// Cache of FFT plans, one for every size that has been requested so far
+#[derive(Debug, Default)]
+pub struct FftPlanner {
+    plans: HashMap<usize, RealFft>,
+}
+
+fn unit_root(k: usize, n: usize) -> Complex<f32> {
+    // computed in f64 so that the tables don't accumulate rounding errors
+    let angle = -2.0 * PI * (k as f64) / (n as f64);
+    Complex::new(angle.cos() as f32, angle.sin() as f32)
+}
+
+impl RealFft {
+    /// Return a plan for transforming n real samples, n has to be a power of two not smaller than 2
+    pub fn new(n: usize) -> Self {
+        assert!(
+            n >= 2 && n.is_power_of_two(),
+            "FFT size must be a power of two"
+        );
+        // n real samples are packed into n / 2 complex ones, transformed and then split apart
+        let m = n / 2;
+        let bits = m.trailing_zeros();
+        let bit_reversed = (0..m)
+            .map(|k| match bits {
+                0 => 0,
+                _ => k.reverse_bits() >> (usize::BITS - bits),
+            })
+            .collect::<Vec<_>>();
+        let twiddles = (0..(m / 2)).map(|k| unit_root(k, m)).collect::<Vec<_>>();
+        let split_twiddles = (0..m).map(|k| unit_root(k, n)).collect::<Vec<_>>();
+
+        RealFft {
+            n,
+            bit_reversed,
+            twiddles,
+            split_twiddles,
+            buf: vec![Complex::new(0.0, 0.0); m],
+            output: vec![Complex::new(0.0, 0.0); m + 1],
+        }
+    }
+
+    /// Transform the samples (zero-padded up to the size of the plan) and return
+    /// the first n / 2 + 1 bins of the spectrum, the rest of them are their complex conjugates
+    pub fn transform(&mut self, samples: &[f32]) -> &[Complex<f32>] {
+        assert!(
+            samples.len() <= self.n,
+            "Too many samples for this FFT plan"
+        );
+        let m = self.n / 2;
+        let sample_at = |i: usize| samples.get(i).copied().unwrap_or(0.0);
+        for k in 0..m {
+            self.buf[self.bit_reversed[k]] = Complex::new(sample_at(2 * k), sample_at(2 * k + 1));
+        }
+
+        // iterative radix-2 butterflies on the bit-reversed buffer
+        let mut len = 2;
+        while len <= m {
+            let (half, step) = (len / 2, m / len);
+            for block in self.buf.chunks_exact_mut(len) {
+                let (lo, hi) = block.split_at_mut(half);
+                for j in 0..half {
+                    let t = self.twiddles[j * step] * hi[j];
+                    hi[j] = lo[j] - t;
+                    lo[j] += t;
+                }
+            }
+            len *= 2;
+        }
+
+        // separate the spectra of the even and odd samples and combine them into the spectrum of the whole signal
+        for k in 0..=m {
+            let z = self.buf[k % m];
+            let z_mirrored = self.buf[(m - k) % m].conj();
+            let even = 0.5 * (z + z_mirrored);
+            let odd = Complex::new(0.0, -0.5) * (z - z_mirrored);
+            let twiddle = if k == m {
+                Complex::new(-1.0, 0.0)
+            } else {
+                self.split_twiddles[k]
+            };
+            self.output[k] = even + twiddle * odd;
+        }
+
+        &self.output
+    }
+}
+
+impl FftPlanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the plan for an FFT of size n, creating it the first time that size is requested
+    pub fn plan(&mut self, n: usize) -> &mut RealFft {
+        self.plans.entry(n).or_insert_with(|| RealFft::new(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // kept inside the test, as benches/fft.rs includes this file without running its tests
+    #[test]
+    fn transform_matches_naive_dft() {
+        use super::*;
+
+        /// First n / 2 + 1 bins of the DFT of the samples zero-padded to n, computed straight from the definition
+        fn naive_dft(samples: &[f32], n: usize) -> Vec<Complex<f64>> {
+            (0..=(n / 2))
+                .map(|k| {
+                    samples
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &x)| {
+                            let angle = -2.0 * PI * ((i * k) % n) as f64 / n as f64;
+                            Complex::from_polar(x as f64, angle)
+                        })
+                        .sum::<Complex<f64>>()
+                })
+                .collect::<Vec<_>>()
+        }
+
+        // (plan size, number of samples), the latter being smaller means zero-padding
+        for (n, n_samples) in [(2, 2), (4, 3), (8, 8), (64, 64), (256, 200), (1024, 1024)] {
+            // a deterministic mix of a few sines and a ramp
+            let samples = (0..n_samples)
+                .map(|i| {
+                    let t = i as f32 / n_samples as f32;
+                    (7.0 * t).sin() + 0.5 * (31.0 * t).cos() - 0.25 * t
+                })
+                .collect::<Vec<_>>();
+            let expected = naive_dft(&samples, n);
+            let mut fft = RealFft::new(n);
+            let spectrum = fft.transform(&samples);
+            assert_eq!(spectrum.len(), n / 2 + 1);
+            // the magnitudes of the bins grow with n, so the error is taken relative to it
+            let max_error = spectrum
+                .iter()
+                .zip(expected.iter())
+                .map(|(x, y)| (Complex::new(x.re as f64, x.im as f64) - y).norm() / n as f64)
+                .fold(0.0, f64::max);
+            assert!(
+                max_error < 1e-4,
+                "n = {}, {} samples: error {}",
+                n,
+                n_samples,
+                max_error
+            );
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+/// Median of the values (reordering them)
+fn median(values: &mut [f32]) -> f32 {
+    let mid = values.len() / 2;
+    *values
+        .select_nth_unstable_by(mid, |a, b| {
+            a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .1
+}
+
+/// Harmonic/percussive source separation by median filtering, sustained sounds are smooth over time
+/// while drum hits are smooth over frequency
+#[derive(Debug)]
+pub struct Hpss {
+    history: VecDeque<Vec<f32>>,
+    history_len: usize,
+    kernel_bins: usize,
+}
+
+impl Hpss {
+    /// Return a separator median-filtering over the last history_len frames and over kernel_bins neighboring bins
+    pub fn new(history_len: usize, kernel_bins: usize) -> Self {
+        Hpss {
+            history: VecDeque::with_capacity(history_len + 1),
+            history_len: history_len.max(1),
+            kernel_bins: kernel_bins.max(1),
+        }
+    }
+
+    /// Feed the spectrum of the next frame, returning its harmonic and percussive parts
+    pub fn separate(&mut self, spectrum: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        self.history.push_back(spectrum.to_vec());
+        if self.history.len() > self.history_len {
+            self.history.pop_front();
+        }
+
+        let n_bins = spectrum.len();
+        let mut values = Vec::with_capacity(self.history_len.max(self.kernel_bins));
+        let harmonic = (0..n_bins)
+            .map(|i| {
+                values.clear();
+                values.extend(self.history.iter().map(|frame| frame[i]));
+                median(&mut values)
+            })
+            .collect::<Vec<_>>();
+        let percussive = (0..n_bins)
+            .map(|i| {
+                let half = self.kernel_bins / 2;
+                values.clear();
+                values.extend_from_slice(
+                    &spectrum[i.saturating_sub(half)..(i + half + 1).min(n_bins)],
+                );
+                median(&mut values)
+            })
+            .collect::<Vec<_>>();
+
+        // soft (Wiener) masks, so that the two parts add up to the whole spectrum
+        spectrum
+            .iter()
+            .zip(harmonic.iter().zip(percussive.iter()))
+            .map(|(x, (h, p))| {
+                let (h2, p2) = (h * h, p * p);
+                if h2 + p2 > 0.0 {
+                    (x * h2 / (h2 + p2), x * p2 / (h2 + p2))
+                } else {
+                    (0.5 * x, 0.5 * x)
+                }
+            })
+            .unzip()
+    }
+}
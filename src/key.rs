@@ -0,0 +1,174 @@
+use crate::chroma::N_PITCH_CLASSES;
+use crate::envelope::smoothing_coeff;
+use std::fmt;
+
+// Krumhansl-Kessler key profiles, starting from the tonic
+const MAJOR_PROFILE: [f32; N_PITCH_CLASSES] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; N_PITCH_CLASSES] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+// how long (in seconds) the music has to play before the key is estimated for the first time
+const WARMUP_TIME: f32 = 5.0;
+const NOTE_NAMES: [&str; N_PITCH_CLASSES] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    pub tonic: usize,
+    pub minor: bool,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.minor {
+            write!(f, "{} minor", NOTE_NAMES[self.tonic])
+        } else {
+            write!(f, "{} major", NOTE_NAMES[self.tonic])
+        }
+    }
+}
+
+impl Key {
+    /// Hue of the key on a circle-of-fifths color wheel, minor keys share the hue of their relative major
+    pub fn hue(&self) -> f32 {
+        let major_tonic = if self.minor {
+            (self.tonic + 3) % N_PITCH_CLASSES
+        } else {
+            self.tonic
+        };
+        let fifths = (major_tonic * 7) % N_PITCH_CLASSES;
+
+        (fifths * 360 / N_PITCH_CLASSES) as f32
+    }
+
+    /// Hues of n_panels panels, spread over hue_span degrees starting from the key's hue
+    pub fn palette(&self, n_panels: usize, hue_span: f32) -> Vec<f32> {
+        (0..n_panels)
+            .map(|i| {
+                let offset = match n_panels {
+                    1 => 0.0,
+                    _ => hue_span * (i as f32) / ((n_panels - 1) as f32),
+                };
+                (self.hue() + offset).rem_euclid(360.0)
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+fn correlation(x: &[f32; N_PITCH_CLASSES], y: &[f32; N_PITCH_CLASSES]) -> f32 {
+    let n = N_PITCH_CLASSES as f32;
+    let (mean_x, mean_y) = (x.iter().sum::<f32>() / n, y.iter().sum::<f32>() / n);
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y.iter()) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x) * (a - mean_x);
+        var_y += (b - mean_y) * (b - mean_y);
+    }
+
+    cov / (var_x * var_y).sqrt().max(f32::EPSILON)
+}
+
+/// Key estimator matching a long-term average of the chromagram against major and minor key profiles
+#[derive(Debug)]
+pub struct KeyEstimator {
+    smoothing: f32,
+    chroma: [f32; N_PITCH_CLASSES],
+    warmup_frames: usize,
+}
+
+impl KeyEstimator {
+    /// Return an estimator for frames analyzed every frame_duration seconds, averaging over smoothing_time seconds
+    pub fn new(frame_duration: f32, smoothing_time: f32) -> Self {
+        KeyEstimator {
+            smoothing: smoothing_coeff(frame_duration, smoothing_time),
+            chroma: [0.0; N_PITCH_CLASSES],
+            warmup_frames: (WARMUP_TIME / frame_duration).ceil() as usize,
+        }
+    }
+
+    /// Feed the chromagram of the next frame, returning the most likely key so far (if there's been any music)
+    pub fn update(&mut self, chroma: &[f32; N_PITCH_CLASSES]) -> Option<Key> {
+        for (avg, x) in self.chroma.iter_mut().zip(chroma.iter()) {
+            *avg += self.smoothing * (x - *avg);
+        }
+        if self.chroma.iter().all(|&x| x < f32::EPSILON) {
+            return None;
+        }
+        if self.warmup_frames > 0 {
+            self.warmup_frames -= 1;
+            return None;
+        }
+
+        let rotated = |profile: &[f32; N_PITCH_CLASSES], tonic: usize| {
+            let mut rotated = [0.0; N_PITCH_CLASSES];
+            for (pitch_class, x) in rotated.iter_mut().enumerate() {
+                *x = profile[(pitch_class + N_PITCH_CLASSES - tonic) % N_PITCH_CLASSES];
+            }
+            rotated
+        };
+        (0..N_PITCH_CLASSES)
+            .flat_map(|tonic| [(tonic, false), (tonic, true)])
+            .map(|(tonic, minor)| {
+                let profile = if minor {
+                    &MINOR_PROFILE
+                } else {
+                    &MAJOR_PROFILE
+                };
+                (
+                    Key { tonic, minor },
+                    correlation(&self.chroma, &rotated(profile, tonic)),
+                )
+            })
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(key, _)| key)
+    }
+}
+
+/// Gradual transition from one palette to another
+#[derive(Debug)]
+pub struct Crossfade {
+    from: Vec<f32>,
+    to: Vec<f32>,
+    progress: f32,
+    step: f32,
+}
+
+impl Crossfade {
+    /// Return a crossfade (initially finished) for frames analyzed every frame_duration seconds,
+    /// lasting crossfade_time seconds
+    pub fn new(palette: Vec<f32>, frame_duration: f32, crossfade_time: f32) -> Self {
+        Crossfade {
+            from: palette.clone(),
+            to: palette,
+            progress: 1.0,
+            step: (frame_duration / crossfade_time.max(frame_duration)).min(1.0),
+        }
+    }
+
+    /// Start fading from the current hues into the palette
+    pub fn start(&mut self, palette: Vec<f32>) {
+        self.from = self.current();
+        self.to = palette;
+        self.progress = 0.0;
+    }
+
+    /// Advance the crossfade by one frame
+    pub fn advance(&mut self) {
+        self.progress = (self.progress + self.step).min(1.0);
+    }
+
+    /// The current hues, interpolated the shorter way around the color wheel
+    pub fn current(&self) -> Vec<f32> {
+        self.from
+            .iter()
+            .zip(self.to.iter())
+            .map(|(from, to)| {
+                let diff = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+                (from + self.progress * diff).rem_euclid(360.0)
+            })
+            .collect::<Vec<_>>()
+    }
+}
@@ -0,0 +1,183 @@
+use crate::biquad::Biquad;
+use crate::envelope::Envelopes;
+use palette::Hwb;
+use std::collections::VecDeque;
+
+// length (in seconds) of the steps in which the loudness is measured, the windows overlap by all but one step
+const STEP_TIME: f32 = 0.1;
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+// blocks quieter than this (in LUFS) are left out of the integrated loudness,
+// as are blocks quieter than the relative gate (in LU) below the loudness of the remaining ones
+const ABSOLUTE_GATE: f32 = -70.0;
+const RELATIVE_GATE: f32 = -10.0;
+// weight of the surround channels, +1.5 dB
+const SURROUND_WEIGHT: f32 = 1.41;
+
+fn to_lufs(power: f32) -> f32 {
+    -0.691 + 10.0 * power.max(f32::MIN_POSITIVE).log10()
+}
+
+fn to_power(lufs: f32) -> f32 {
+    10f32.powf((lufs + 0.691) / 10.0)
+}
+
+/// The two stages of the K-weighting filter, derived for any sample rate so that they match
+/// the coefficients given in BS.1770 for 48 kHz
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let k = |freq: f64| (std::f64::consts::PI * freq / sample_rate as f64).tan();
+    // models the acoustic effect of the head
+    let (shelf_k, shelf_q) = (k(1681.974450955533), 0.7071752369554196);
+    let (vh, vb) = (1.584864701130855, 1.258720930232562);
+    let shelf = Biquad::new(
+        [
+            vh + vb * shelf_k / shelf_q + shelf_k * shelf_k,
+            2.0 * (shelf_k * shelf_k - vh),
+            vh - vb * shelf_k / shelf_q + shelf_k * shelf_k,
+        ],
+        [
+            1.0 + shelf_k / shelf_q + shelf_k * shelf_k,
+            2.0 * (shelf_k * shelf_k - 1.0),
+            1.0 - shelf_k / shelf_q + shelf_k * shelf_k,
+        ],
+    );
+    // the revised low-frequency B-curve, a high-pass
+    let (hp_k, hp_q) = (k(38.13547087602444), 0.5003270373238773);
+    let a0 = 1.0 + hp_k / hp_q + hp_k * hp_k;
+    let high_pass = Biquad::new(
+        [a0, -2.0 * a0, a0],
+        [
+            a0,
+            2.0 * (hp_k * hp_k - 1.0),
+            1.0 - hp_k / hp_q + hp_k * hp_k,
+        ],
+    );
+
+    [shelf, high_pass]
+}
+
+/// Weights of the channels in BS.1770, assuming the usual (WAV/SMPTE) order of 5.1 and 7.1 layouts:
+/// L, R, C, LFE and then the surround channels, which are weighted higher, while the LFE is left out,
+/// channels of other layouts are weighted equally
+fn channel_weights(n_channels: usize) -> Vec<f32> {
+    match n_channels {
+        6 | 8 => (0..n_channels)
+            .map(|channel| match channel {
+                0..=2 => 1.0,
+                3 => 0.0,
+                _ => SURROUND_WEIGHT,
+            })
+            .collect::<Vec<_>>(),
+        _ => vec![1.0; n_channels],
+    }
+}
+
+/// Loudness meter following EBU R128 (ITU-R BS.1770)
+#[derive(Debug)]
+pub struct LoudnessMeter {
+    // K-weighting filters of every channel
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f32>,
+    step_len: usize,
+    step_pos: usize,
+    step_power: f32,
+    // mean square of the filtered samples in the most recent steps, a weighted sum over all channels
+    steps: VecDeque<f32>,
+    // powers of all momentary blocks that pass the absolute gate
+    blocks: Vec<f32>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, n_channels: usize) -> Self {
+        LoudnessMeter {
+            filters: vec![k_weighting(sample_rate); n_channels],
+            weights: channel_weights(n_channels),
+            step_len: ((STEP_TIME * sample_rate as f32).round() as usize).max(1),
+            step_pos: 0,
+            step_power: 0.0,
+            steps: VecDeque::with_capacity(SHORT_TERM_STEPS + 1),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Feed interleaved samples of all channels
+    pub fn push(&mut self, samples: &[f32]) {
+        let n_channels = self.filters.len();
+        for frame in samples.chunks_exact(n_channels) {
+            for ((x, [shelf, high_pass]), weight) in frame
+                .iter()
+                .zip(self.filters.iter_mut())
+                .zip(self.weights.iter())
+            {
+                let y = high_pass.process(shelf.process(*x));
+                self.step_power += weight * y * y;
+            }
+            self.step_pos += 1;
+            if self.step_pos == self.step_len {
+                self.end_step();
+            }
+        }
+    }
+
+    fn end_step(&mut self) {
+        self.steps.push_back(self.step_power / self.step_len as f32);
+        if self.steps.len() > SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        (self.step_pos, self.step_power) = (0, 0.0);
+        if let Some(power) = self.mean_power(MOMENTARY_STEPS) {
+            if to_lufs(power) > ABSOLUTE_GATE {
+                self.blocks.push(power);
+            }
+        }
+    }
+
+    /// Mean power of the last n_steps steps, if there have been that many
+    fn mean_power(&self, n_steps: usize) -> Option<f32> {
+        (n_steps <= self.steps.len())
+            .then(|| self.steps.iter().rev().take(n_steps).sum::<f32>() / n_steps as f32)
+    }
+
+    /// Loudness (in LUFS) over the last 400 ms
+    pub fn momentary(&self) -> Option<f32> {
+        self.mean_power(MOMENTARY_STEPS).map(to_lufs)
+    }
+
+    /// Loudness (in LUFS) over the last 3 s
+    pub fn short_term(&self) -> Option<f32> {
+        self.mean_power(SHORT_TERM_STEPS).map(to_lufs)
+    }
+
+    /// Gated loudness (in LUFS) since the meter was started
+    pub fn integrated(&self) -> Option<f32> {
+        if self.blocks.is_empty() {
+            return None;
+        }
+        let mean = self.blocks.iter().sum::<f32>() / self.blocks.len() as f32;
+        let relative_gate = to_power(to_lufs(mean) + RELATIVE_GATE);
+        let (sum, count) = self
+            .blocks
+            .iter()
+            .filter(|&&power| power > relative_gate)
+            .fold((0.0, 0), |(sum, count), power| (sum + power, count + 1));
+
+        (count > 0).then(|| to_lufs(sum / count as f32))
+    }
+}
+
+/// Light up the panels one after another like a VU meter, the level being between 0 and 1
+pub fn update_colors(colors: &mut [Hwb], level: f32, envelopes: &mut Envelopes) {
+    let n_panels = colors.len();
+    for (i, color) in colors.iter_mut().enumerate() {
+        let ampl = (level * n_panels as f32 - i as f32).clamp(0.0, 1.0);
+        color.whiteness = 1.0 - envelopes.update(i, ampl);
+    }
+}
+
+/// Dim the colors as if they were mixed with black, the level being between 0 (black) and 1 (unchanged)
+pub fn dim(colors: &mut [Hwb], level: f32) {
+    for color in colors.iter_mut() {
+        color.whiteness *= level;
+        color.blackness = 1.0 - (1.0 - color.blackness) * level;
+    }
+}
@@ -1,18 +1,58 @@
-use clap::Parser;
-use config::{Axis, Config, NlConfig, Sort};
+use agc::Agc;
+use audio::Analyzer;
+use audio::Component;
+use biquad::FilterChain;
+use chroma::ChromaMap;
+use clap::{Parser, Subcommand};
+use config::{
+    AgcConfig, AmplitudeMapping, Analysis, Axis, BandScale, ChromaConfig, Config, CqtConfig,
+    Downmix, EnvelopeConfig, EqConfig, Filter, HpssConfig, KeyConfig, LoudnessConfig,
+    LoudnessWindow, Mode, NlConfig, OnsetConfig, PaletteStep, Sort, StereoConfig, TempoConfig,
+    Window,
+};
 use console::Term;
 use core::f32;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{InputCallbackInfo, Sample, StreamConfig};
+use cqt::ConstantQ;
+use descriptors::{DescriptorSmoother, Descriptors};
+use envelope::{smoothing_coeff, Envelopes};
+use fft::FftPlanner;
+use hpss::Hpss;
+use key::{Crossfade, KeyEstimator};
+use loudness::LoudnessMeter;
 use nanoleaf::{Command, Nanoleaf, Panel};
+use onset::OnsetDetector;
+use signal::TestSignal;
+use source::{AudioSource, PcmFormat};
 use std::net::Ipv4Addr;
 use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use tempo::{Beat, TempoTracker};
 
+mod agc;
 mod audio;
+mod biquad;
+mod chroma;
 mod config;
+mod cqt;
+mod descriptors;
+mod envelope;
+mod eq;
+mod fft;
+mod hpss;
+mod key;
+mod loudness;
 mod nanoleaf;
+mod onset;
+mod rtp;
+mod signal;
+mod source;
+mod tempo;
+
+// how often the loudness is printed, each time on a line of its own so that it doesn't get mixed up
+// with the other messages
+const LOUDNESS_DISPLAY_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Audioleaf - An audio visualizer for Nanoleaf Canvas
 #[derive(Parser, Debug)]
@@ -37,6 +77,67 @@ struct CmdOpt {
     /// Audio input device to serve as the source of audio data
     #[arg(short, long)]
     audio_device: Option<String>,
+
+    /// Audio file (WAV, FLAC, Ogg Vorbis, ...) to visualize instead of the input device, `-` reads raw PCM from stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Address (e.g. 0.0.0.0:5004) on which to receive L16 RTP (sent by `audioleaf send`) instead of using the input device
+    #[arg(short, long, conflicts_with = "input")]
+    listen: Option<String>,
+
+    /// Synthetic signal to visualize instead of the input device: sweep[:from:to[:secs]], tones:f1,f2,...[:secs],
+    /// chord:f1,f2,..., clicks[:bpm], white or pink (frequencies in Hz)
+    #[arg(long, conflicts_with_all = ["input", "listen"])]
+    test_signal: Option<TestSignal>,
+
+    /// Delay (in ms) of the audio received over the network, so that packets arriving late still make it in time
+    #[arg(long, default_value_t = 40)]
+    jitter: u64,
+
+    /// Play the input as fast as possible instead of at real-time pace
+    #[arg(long)]
+    no_realtime: bool,
+
+    /// Sample format of raw PCM read from stdin
+    #[arg(long, value_enum, default_value_t = PcmFormat::S16le)]
+    format: PcmFormat,
+
+    /// Sample rate (in Hz) of raw PCM read from stdin or received over the network, or of the test signal
+    #[arg(long, default_value_t = 44100)]
+    rate: u32,
+
+    /// Number of interleaved channels of raw PCM read from stdin or received over the network, or of the test signal
+    #[arg(long, default_value_t = 2)]
+    channels: usize,
+
+    #[command(subcommand)]
+    command: Option<CmdCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CmdCommand {
+    /// Stream an input device to audioleaf running on another machine with `--listen`
+    Send {
+        /// Address (e.g. 192.168.1.20:5004) to which the audio will be sent
+        address: String,
+
+        /// Audio input device to stream
+        #[arg(short, long, default_value = "default")]
+        audio_device: String,
+    },
+}
+
+/// Messages received by the visualizer thread
+enum Message {
+    /// Interleaved samples of all input channels and the samples of every visualized channel
+    Samples {
+        input: Vec<f32>,
+        visualized: Vec<Vec<f32>>,
+    },
+    Tap,
+    ResetTempo,
+    Quit,
 }
 
 fn main() -> Result<(), anyhow::Error> {
@@ -46,7 +147,23 @@ fn main() -> Result<(), anyhow::Error> {
         ip: nl_ip,
         port: nl_port,
         audio_device: device_name,
+        input,
+        listen,
+        test_signal,
+        jitter,
+        no_realtime,
+        format: pcm_format,
+        rate: pcm_rate,
+        channels: pcm_channels,
+        command,
     } = CmdOpt::parse();
+    if let Some(CmdCommand::Send {
+        address,
+        audio_device,
+    }) = command
+    {
+        return rtp::send(&address, &audio_device);
+    }
     let config_file_path =
         config_file_path.unwrap_or(dirs::config_dir().unwrap().join("audioleaf/audioleaf.toml"));
 
@@ -91,7 +208,7 @@ fn main() -> Result<(), anyhow::Error> {
                     nl.panels.len()
                 )));
             }
-            if config.nl_config.active_panels.iter().any(|&x| x == 0) {
+            if config.nl_config.active_panels.contains(&0) {
                 return Err(anyhow::Error::msg(
                     "Panels should be numbered starting from 1",
                 ));
@@ -141,15 +258,38 @@ fn main() -> Result<(), anyhow::Error> {
             let config = Config {
                 nl_config,
                 audio_device: device_name.unwrap_or(String::from("default")),
+                mpd_fifo: None,
+                downmix: Downmix::Mean,
+                mode: Mode::Bands,
                 min_freq: 20,
                 max_freq: 6000,
+                band_scale: BandScale::Log,
                 default_gain: 0.5,
+                agc: AgcConfig::default(),
+                amplitude_mapping: AmplitudeMapping::Sigmoid,
+                noise_floor_secs: 0.0,
+                filters: Vec::new(),
+                eq: EqConfig::default(),
+                envelope: EnvelopeConfig::default(),
                 transition_time: 2,
                 hues: (240..=420)
                     .rev()
                     .step_by(180 / (nl.panels.len() - 1))
                     .map(|x| x % 360)
                     .collect::<Vec<u16>>(),
+                analysis: Analysis::Fft,
+                cqt: CqtConfig::default(),
+                window: Window::Hann,
+                fft_size: config::default_fft_size(),
+                hop_size: config::default_hop_size(),
+                onset: OnsetConfig::default(),
+                tempo: TempoConfig::default(),
+                stereo: StereoConfig::default(),
+                hpss: HpssConfig::default(),
+                loudness: LoudnessConfig::default(),
+                chroma: ChromaConfig::default(),
+                key: KeyConfig::default(),
+                descriptors: Vec::new(),
             };
             config::make_new_config_file(&config, &config_file_path)?;
             println!(
@@ -165,11 +305,34 @@ fn main() -> Result<(), anyhow::Error> {
     let Config {
         nl_config,
         audio_device: device_name,
+        mpd_fifo,
+        downmix,
+        mode,
         min_freq,
         max_freq,
+        band_scale,
         default_gain,
+        agc,
+        amplitude_mapping,
+        noise_floor_secs,
+        filters,
+        eq,
+        envelope,
         transition_time,
         mut hues,
+        analysis,
+        cqt,
+        window,
+        fft_size,
+        hop_size,
+        onset,
+        tempo,
+        stereo,
+        hpss,
+        loudness,
+        chroma,
+        key,
+        descriptors: descriptor_mappings,
     } = config;
     let NlConfig {
         primary_axis,
@@ -215,196 +378,552 @@ fn main() -> Result<(), anyhow::Error> {
     };
     nl.sort_panels(|a: &Panel, b: &Panel| sort_func(*a, *b));
 
-    let host = cpal::default_host();
-    let device = match device_name.as_str() {
-        "default" => host.default_input_device(),
-        _ => host
-            .input_devices()?
-            .find(|x| x.name().map(|y| y == device_name).unwrap_or(false)),
-    };
-    let device = match device {
-        Some(device) => device,
-        None => {
-            return Err(anyhow::Error::msg(format!(
-                "Input device '{}' not found, available input devices: {}",
-                device_name,
-                host.input_devices()?.fold(String::new(), |acc, dev| acc
-                    + &dev.name().unwrap_or_default()
-                    + ", ")
-            )));
+    let source = match input {
+        Some(path) if path.as_os_str() == "-" => {
+            AudioSource::open_stdin(pcm_format, pcm_rate, pcm_channels, !no_realtime)?
         }
+        Some(path) => AudioSource::open_file(&path, !no_realtime)?,
+        None => match (listen, test_signal, mpd_fifo) {
+            (Some(address), _, _) => AudioSource::open_network(
+                &address,
+                pcm_rate,
+                pcm_channels,
+                Duration::from_millis(jitter),
+            )?,
+            (None, Some(signal), _) => {
+                AudioSource::open_test_signal(signal, pcm_rate, pcm_channels)?
+            }
+            (None, None, Some(mpd_fifo)) => {
+                AudioSource::open_mpd_fifo(&mpd_fifo.path, &mpd_fifo.format)?
+            }
+            (None, None, None) => AudioSource::open_device(&device_name)?,
+        },
     };
-    let audio_config = device.default_input_config()?;
-    let sample_format = audio_config.sample_format();
-    let audio_config: StreamConfig = audio_config.into();
-    if max_freq > audio_config.sample_rate.0 / 2 {
+    let sample_rate = source.sample_rate();
+    let n_input_channels = source.n_channels();
+    // the stereo mode doesn't downmix, so a leftover channel doesn't matter there
+    if !stereo.enabled {
+        if let Downmix::Channel(channel) = downmix {
+            if channel >= n_input_channels {
+                return Err(anyhow::Error::msg(format!(
+                    "Channel {} selected for downmixing, but the input has only {} channels (numbered from 0)",
+                    channel, n_input_channels
+                )));
+            }
+        }
+    }
+    if max_freq > sample_rate / 2 {
         return Err(anyhow::Error::msg(format!(
             "Maximal frequency to visualize ({} Hz) must be less than half of the sample rate ({} Hz)",
-            max_freq, audio_config.sample_rate.0
+            max_freq, sample_rate
         )));
     }
+    if min_freq < 1 || min_freq >= max_freq {
+        return Err(anyhow::Error::msg(
+            "Minimal frequency to visualize must be positive and less than the maximal one",
+        ));
+    }
 
     if transition_time < 1 {
         return Err(anyhow::Error::msg("Transition time must be positive"));
     }
+    if fft_size < 2 || !fft_size.is_power_of_two() {
+        return Err(anyhow::Error::msg(
+            "FFT size must be a power of two greater than 1",
+        ));
+    }
+    if hop_size < 1 || hop_size > fft_size {
+        return Err(anyhow::Error::msg(
+            "Hop size must be positive and not greater than the FFT size",
+        ));
+    }
+    if cqt.bins_per_octave < 1 {
+        return Err(anyhow::Error::msg(
+            "Number of constant-Q bins per octave must be positive",
+        ));
+    }
+    for filter in filters.iter() {
+        let (Filter::HighPass { freq, q }
+        | Filter::LowPass { freq, q }
+        | Filter::BandPass { freq, q }
+        | Filter::Peaking { freq, q, .. }
+        | Filter::LowShelf { freq, q, .. }
+        | Filter::HighShelf { freq, q, .. }) = *filter;
+        if freq <= 0.0 || freq >= sample_rate as f32 / 2.0 || q <= 0.0 {
+            return Err(anyhow::Error::msg(format!(
+                "Filter {:?} must have a positive Q and a frequency between 0 and half of the sample rate ({} Hz)",
+                filter,
+                sample_rate / 2
+            )));
+        }
+    }
+    if eq.points.iter().any(|&(freq, _)| freq <= 0.0) {
+        return Err(anyhow::Error::msg(
+            "Frequencies of the EQ points must be positive",
+        ));
+    }
+    if loudness.floor_lufs >= loudness.ceiling_lufs {
+        return Err(anyhow::Error::msg(
+            "Loudness floor must be lower than the ceiling",
+        ));
+    }
+    if let AmplitudeMapping::Db {
+        floor_db,
+        ceiling_db,
+    } = amplitude_mapping
+    {
+        if floor_db >= ceiling_db {
+            return Err(anyhow::Error::msg(
+                "Amplitude mapping floor must be lower than the ceiling",
+            ));
+        }
+    }
 
-    let (tx_audio, rx) = mpsc::channel();
-    let tx_user_input = tx_audio.clone();
-    let error_callback =
-        move |err| eprintln!("An error has occured while playing the stream: {}", err);
-    let stream = match sample_format {
-        cpal::SampleFormat::F32 => device.build_input_stream(
-            &audio_config,
-            move |data, _: &InputCallbackInfo| {
-                data_callback(data.to_vec(), audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::F64 => device.build_input_stream(
-            &audio_config,
-            move |data: &[f64], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::I8 => device.build_input_stream(
-            &audio_config,
-            move |data: &[i8], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => device.build_input_stream(
-            &audio_config,
-            move |data: &[i16], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::I32 => device.build_input_stream(
-            &audio_config,
-            move |data: &[i32], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::I64 => device.build_input_stream(
-            &audio_config,
-            move |data: &[i64], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::U8 => device.build_input_stream(
-            &audio_config,
-            move |data: &[u8], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::U16 => device.build_input_stream(
-            &audio_config,
-            move |data: &[u16], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::U32 => device.build_input_stream(
-            &audio_config,
-            move |data: &[u32], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        cpal::SampleFormat::U64 => device.build_input_stream(
-            &audio_config,
-            move |data: &[u64], _: &InputCallbackInfo| {
-                let data_f32 = Vec::from_iter(data.iter().map(|sample| sample.to_sample::<f32>()));
-                data_callback(data_f32, audio_config.channels as usize, &tx_audio)
-            },
-            error_callback,
-            None,
-        )?,
-        sample_format => {
+    if !(0.0..1.0).contains(&envelope.hysteresis) {
+        return Err(anyhow::Error::msg(
+            "Envelope hysteresis must be between 0 and 1",
+        ));
+    }
+
+    // panels visualizing the left and the right channel or all panels visualizing a mix of all channels
+    let panel_groups = if stereo.enabled {
+        let coords = active_panels
+            .iter()
+            .map(|&panel_no| {
+                let panel = nl.panels[panel_no - 1];
+                match stereo.split_axis {
+                    Axis::X => panel.x,
+                    Axis::Y => panel.y,
+                }
+            })
+            .collect::<Vec<_>>();
+        let split_at = stereo.split_at.unwrap_or_else(|| {
+            let (min, max) = (coords.iter().min().unwrap(), coords.iter().max().unwrap());
+            ((*min as i32 + *max as i32) / 2) as i16
+        });
+        let (left, right): (Vec<usize>, Vec<usize>) =
+            (0..active_panels.len()).partition(|&i| coords[i] < split_at);
+        if left.is_empty() || right.is_empty() {
             return Err(anyhow::Error::msg(format!(
-                "Unsupported sample format: {}",
-                sample_format
+                "Splitting the panels at {} leaves no panels for one of the channels",
+                split_at
             )));
         }
+        vec![(left, 0), (right, 1)]
+    } else {
+        vec![((0..active_panels.len()).collect::<Vec<_>>(), 0)]
     };
-    stream.play()?;
+    let n_channels = panel_groups.len();
+    let stereo_input = stereo.enabled;
+    // panels showing only the harmonic or the percussive part of their channel
+    let position = |panel_no: &usize| active_panels.iter().position(|x| x == panel_no);
+    for panel_no in hpss
+        .harmonic_panels
+        .iter()
+        .chain(hpss.percussive_panels.iter())
+    {
+        if position(panel_no).is_none() {
+            return Err(anyhow::Error::msg(format!(
+                "Panel {} is assigned a harmonic/percussive part, but it's not in active_panels",
+                panel_no
+            )));
+        }
+    }
+    if let Some(panel_no) = hpss
+        .harmonic_panels
+        .iter()
+        .find(|panel_no| hpss.percussive_panels.contains(panel_no))
+    {
+        return Err(anyhow::Error::msg(format!(
+            "Panel {} can't visualize both the harmonic and the percussive part",
+            panel_no
+        )));
+    }
+    let component_of = |i: usize| {
+        let is_in = |panel_nos: &[usize]| {
+            panel_nos
+                .iter()
+                .any(|panel_no| position(panel_no) == Some(i))
+        };
+        if hpss.enabled && is_in(&hpss.harmonic_panels) {
+            Component::Harmonic
+        } else if hpss.enabled && is_in(&hpss.percussive_panels) {
+            Component::Percussive
+        } else {
+            Component::Whole
+        }
+    };
+    let panel_groups = panel_groups
+        .into_iter()
+        .flat_map(|(panels, channel)| {
+            [Component::Whole, Component::Harmonic, Component::Percussive]
+                .into_iter()
+                .map(|component| {
+                    let panels = panels
+                        .iter()
+                        .copied()
+                        .filter(|&i| component_of(i) == component)
+                        .collect::<Vec<_>>();
+                    (panels, channel, component)
+                })
+                .filter(|(panels, _, _)| !panels.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let (tx_audio, rx) = mpsc::channel();
+    let tx_user_input = tx_audio.clone();
+    let _source = source.start(move |data| {
+        data_callback(data, n_input_channels, stereo_input, downmix, &tx_audio)
+    })?;
 
     let gain_original = Arc::new(Mutex::new(default_gain));
     let gain = Arc::clone(&gain_original);
+    let agc_enabled_original = Arc::new(Mutex::new(agc.enabled));
+    let agc_enabled = Arc::clone(&agc_enabled_original);
     let visualizer_thread = thread::spawn(move || {
-        let mut colors = hues
+        let n_panels = hues.len();
+        let mut palette = match mode {
+            Mode::Bands | Mode::Vu => hues.into_iter().map(|hue| hue as f32).collect::<Vec<_>>(),
+            // every pitch class has its own hue, regardless of the palette
+            Mode::Chroma => (0..n_panels)
+                .map(|i| chroma::hue(chroma::panel_pitch_classes(i, n_panels).start))
+                .collect::<Vec<_>>(),
+        };
+        let mut rotation = 0;
+        let mut colors = palette
+            .iter()
+            .map(|&hue| palette::Hwb::new(hue, 1.0, 0.0))
+            .collect::<Vec<_>>();
+        let mut analyzer = match analysis {
+            Analysis::Fft => Analyzer::Fft {
+                window: audio::WindowTable::new(window),
+                planner: FftPlanner::new(),
+                fft_size,
+                sample_rate,
+            },
+            Analysis::Cqt => Analyzer::ConstantQ(ConstantQ::new(
+                sample_rate,
+                min_freq,
+                max_freq,
+                cqt.bins_per_octave,
+                window,
+                fft_size,
+            )),
+        };
+        let bins = analyzer.bins();
+        let mut filter_chains = (0..n_channels)
+            .map(|_| FilterChain::new(&filters, sample_rate))
+            .collect::<Vec<_>>();
+        let mut buffers = (0..n_channels)
+            .map(|_| audio::SampleBuffer::new(analyzer.frame_len(), hop_size))
+            .collect::<Vec<_>>();
+        let panel_groups = panel_groups
             .into_iter()
-            .map(|hue| palette::Hwb::new(hue as f32, 1.0, 0.0))
+            .map(|(panels, channel, component)| audio::PanelGroup {
+                bands: audio::map_bands(
+                    &audio::band_edges(band_scale, min_freq, max_freq, panels.len()),
+                    &bins,
+                ),
+                panels,
+                channel,
+                component,
+            })
+            .collect::<Vec<_>>();
+        let chroma_map = ChromaMap::new(&bins, chroma.tuning_hz, min_freq, max_freq);
+        let eq_gains = eq::gains(&bins, eq.preset, &eq.points);
+        let frame_duration = hop_size as f32 / sample_rate as f32;
+        let separate = hpss.enabled
+            && mode == Mode::Bands
+            && panel_groups
+                .iter()
+                .any(|group| group.component != Component::Whole);
+        let mut separators = (0..n_channels)
+            .map(|_| {
+                Hpss::new(
+                    (hpss.history_ms as f32 / 1000.0 / frame_duration).round() as usize,
+                    hpss.kernel_bins,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut onset_detector = OnsetDetector::new(
+            frame_duration,
+            onset.sensitivity,
+            onset.refractory_ms as f32 / 1000.0,
+        );
+        let mut envelopes = Envelopes::new(
+            n_panels,
+            frame_duration,
+            envelope.attack_ms as f32 / 1000.0,
+            envelope.release_ms as f32 / 1000.0,
+            envelope.hold_ms as f32 / 1000.0,
+            envelope.hysteresis,
+        );
+        let flash_decay =
+            1.0 - smoothing_coeff(frame_duration, onset.flash_ms.max(1) as f32 / 1000.0);
+        let mut flash = 0.0;
+        let squash = audio::Squash::new(amplitude_mapping, fft_size);
+        let noise_floor_frames = (noise_floor_secs.max(0.0) / frame_duration).round() as usize;
+        let mut noise_floors = (0..n_channels)
+            .map(|_| audio::NoiseFloor::new(noise_floor_frames))
             .collect::<Vec<_>>();
-        'visualizer_loop: loop {
-            let mut time_samples = Vec::new();
-            for _ in 0..(2 * transition_time) {
-                match rx.recv().unwrap() {
-                    Some(mut samples) => time_samples.append(&mut samples),
-                    None => break 'visualizer_loop,
+        if noise_floor_frames > 0 {
+            println!(
+                "Measuring the noise floor for {} s, keep it quiet...",
+                noise_floor_secs
+            );
+        }
+        let mut auto_gain = Agc::new(
+            frame_duration,
+            agc.attack_ms as f32 / 1000.0,
+            agc.release_ms as f32 / 1000.0,
+            squash.inverse(agc.target_level),
+            agc.max_gain,
+        );
+        // the AGC only looks at the frequencies that are visualized
+        let agc_bins = bins.range(min_freq as f32, max_freq as f32);
+        let mut tempo_tracker = TempoTracker::new(frame_duration, tempo.min_bpm, tempo.max_bpm);
+        // hues are tied to pitch classes in the chroma mode, so they stay in place
+        let steps_palette = |beat: Beat| {
+            mode != Mode::Chroma
+                && match tempo.palette_step {
+                    PaletteStep::Beat => true,
+                    PaletteStep::Bar => beat.downbeat,
+                    PaletteStep::None => false,
+                }
+        };
+        // the key doesn't pick the palette in the chroma mode, for the same reason
+        let key_palette = key.enabled && mode != Mode::Chroma;
+        let mut key_estimator = KeyEstimator::new(frame_duration, key.smoothing_secs);
+        let mut cur_key = None;
+        let mut crossfade = Crossfade::new(palette.clone(), frame_duration, key.crossfade_secs);
+        let mut descriptor_smoother = DescriptorSmoother::new(frame_duration);
+        let mut loudness_meter = (loudness.enabled || loudness.brightness || mode == Mode::Vu)
+            .then(|| LoudnessMeter::new(sample_rate, n_input_channels));
+        let mut last_display = Instant::now();
+        loop {
+            let samples = match rx.recv().unwrap() {
+                Message::Samples { input, visualized } => {
+                    if let Some(meter) = loudness_meter.as_mut() {
+                        meter.push(&input);
+                    }
+                    visualized
+                }
+                Message::Tap => {
+                    if let Some(beat) = tempo_tracker.tap() {
+                        if steps_palette(beat) {
+                            rotation += 1;
+                        }
+                        println!("Tapped tempo: {:.0} BPM", tempo_tracker.bpm().unwrap());
+                    }
+                    continue;
+                }
+                Message::ResetTempo => {
+                    tempo_tracker.reset();
+                    println!("Estimating tempo from audio");
+                    continue;
+                }
+                Message::Quit => break,
+            };
+            for i in 0..samples[0].len() {
+                // all channels get the same number of samples, so their frames are always due at the same time
+                let mut frame_due = false;
+                for ((buffer, filter_chain), channel_samples) in buffers
+                    .iter_mut()
+                    .zip(filter_chains.iter_mut())
+                    .zip(samples.iter())
+                {
+                    frame_due = buffer.push(filter_chain.process(channel_samples[i]));
+                }
+                if !frame_due {
+                    continue;
+                }
+                let mut spectra = buffers
+                    .iter_mut()
+                    .map(|buffer| analyzer.process(buffer.frame()))
+                    .collect::<Vec<_>>();
+                if noise_floors
+                    .iter()
+                    .any(|noise_floor| noise_floor.is_measuring())
+                {
+                    for (noise_floor, spectrum) in noise_floors.iter_mut().zip(spectra.iter()) {
+                        noise_floor.measure(spectrum);
+                    }
+                    if !noise_floors[0].is_measuring() {
+                        println!("Noise floor measured");
+                    }
+                    continue;
+                }
+                for (noise_floor, spectrum) in noise_floors.iter().zip(spectra.iter_mut()) {
+                    noise_floor.subtract(spectrum);
+                    eq::apply(spectrum, &eq_gains);
+                }
+                let mut parts = if separate {
+                    separators
+                        .iter_mut()
+                        .zip(spectra.iter())
+                        .map(|(separator, spectrum)| separator.separate(spectrum))
+                        .collect::<Vec<_>>()
+                } else {
+                    Vec::new()
+                };
+                let cur_gain = if *agc_enabled.lock().unwrap() {
+                    // the same gain for all channels so that the balance between them is kept
+                    let cur_gain = auto_gain.update(&audio::loudest(&spectra)[agc_bins.clone()]);
+                    // so that switching to manual gain starts from where the AGC left off
+                    *gain.lock().unwrap() = cur_gain;
+                    cur_gain
+                } else {
+                    *gain.lock().unwrap()
+                };
+                for spectrum in spectra.iter_mut() {
+                    squash.apply(spectrum, cur_gain);
+                }
+                for (harmonic, percussive) in parts.iter_mut() {
+                    squash.apply(harmonic, cur_gain);
+                    squash.apply(percussive, cur_gain);
+                }
+                let loudest_spectrum = audio::loudest(&spectra);
+                let onset_strength = onset_detector.detect(&loudest_spectrum);
+                if onset.enabled {
+                    flash *= flash_decay;
+                    if let Some(strength) = onset_strength {
+                        flash = f32::max(flash, strength);
+                    }
+                }
+                if let Some(beat) =
+                    tempo_tracker.update(onset_detector.flux(), onset_strength.is_some())
+                {
+                    if steps_palette(beat) {
+                        rotation += 1;
+                    }
+                }
+                if key_palette {
+                    let new_key = key_estimator.update(&chroma_map.chromagram(&loudest_spectrum));
+                    if let Some(new_key) = new_key.filter(|&new_key| Some(new_key) != cur_key) {
+                        println!("Key: {}", new_key);
+                        crossfade.start(new_key.palette(n_panels, key.hue_span));
+                        cur_key = Some(new_key);
+                    }
+                    crossfade.advance();
+                    palette = crossfade.current();
+                }
+                audio::set_hues(&mut colors, &palette, rotation);
+                let loudness_level = loudness_meter
+                    .as_ref()
+                    .and_then(|meter| match loudness.window {
+                        LoudnessWindow::Momentary => meter.momentary(),
+                        LoudnessWindow::ShortTerm => meter.short_term(),
+                    })
+                    .map_or(0.0, |lufs| {
+                        ((lufs - loudness.floor_lufs)
+                            / (loudness.ceiling_lufs - loudness.floor_lufs))
+                            .clamp(0.0, 1.0)
+                    });
+                if let Some(meter) = loudness_meter.as_ref().filter(|_| loudness.enabled) {
+                    if last_display.elapsed() >= LOUDNESS_DISPLAY_INTERVAL {
+                        let show = |lufs: Option<f32>| {
+                            lufs.map_or(String::from("-inf"), |lufs| format!("{:.1}", lufs))
+                        };
+                        println!(
+                            "Loudness: M {} / S {} / I {} LUFS",
+                            show(meter.momentary()),
+                            show(meter.short_term()),
+                            show(meter.integrated())
+                        );
+                        last_display = Instant::now();
+                    }
                 }
-            }
-            let freq_samples = audio::process(time_samples, *gain.lock().unwrap());
 
-            let hz_per_bin = (audio_config.sample_rate.0 / 2) / (freq_samples.len() as u32);
-            audio::update_colors(&mut colors, freq_samples, min_freq, max_freq, hz_per_bin);
-            let commands = active_panels
-                .iter()
-                .zip(colors.iter())
-                .map(|(panel_no, color)| Command {
-                    panel_no: *panel_no,
-                    color: *color,
-                    transition_time,
-                })
-                .collect::<Vec<_>>();
-            nl.run_commands(commands).unwrap();
+                match mode {
+                    Mode::Bands => {
+                        for group in panel_groups.iter() {
+                            let spectrum = match group.component {
+                                Component::Whole => &spectra[group.channel],
+                                Component::Harmonic => &parts[group.channel].0,
+                                Component::Percussive => &parts[group.channel].1,
+                            };
+                            audio::update_colors(
+                                &mut colors,
+                                &group.panels,
+                                spectrum,
+                                &group.bands,
+                                &mut envelopes,
+                            );
+                        }
+                    }
+                    Mode::Chroma => chroma::update_colors(
+                        &mut colors,
+                        &chroma_map.chromagram(&loudest_spectrum),
+                        &mut envelopes,
+                    ),
+                    Mode::Vu => {
+                        loudness::update_colors(&mut colors, loudness_level, &mut envelopes)
+                    }
+                }
+                let mut shown_colors = audio::flash(&colors, flash);
+                if !descriptor_mappings.is_empty() {
+                    let cur_descriptors = descriptor_smoother.update(Descriptors::new(
+                        &loudest_spectrum,
+                        &bins,
+                        min_freq,
+                        max_freq,
+                    ));
+                    descriptors::apply(&mut shown_colors, &descriptor_mappings, &cur_descriptors);
+                }
+                if loudness.brightness {
+                    loudness::dim(&mut shown_colors, loudness_level);
+                }
+                let commands = active_panels
+                    .iter()
+                    .zip(shown_colors.iter())
+                    .map(|(panel_no, color)| Command {
+                        panel_no: *panel_no,
+                        color: *color,
+                        transition_time,
+                    })
+                    .collect::<Vec<_>>();
+                nl.run_commands(commands).unwrap();
+            }
         }
     });
 
     let gain = Arc::clone(&gain_original);
+    let agc_enabled = Arc::clone(&agc_enabled_original);
     let stdout = Term::buffered_stdout();
     'main_loop: loop {
         if let Ok(ch) = stdout.read_char() {
             match ch {
                 'Q' => {
-                    tx_user_input.send(None).unwrap();
+                    tx_user_input.send(Message::Quit).unwrap();
                     visualizer_thread.join().unwrap();
                     println!("Quitting audioleaf...");
                     break 'main_loop;
                 }
                 '=' => {
+                    *agc_enabled.lock().unwrap() = false;
                     let mut gain = gain.lock().unwrap();
                     *gain += 0.05;
                 }
                 '-' => {
+                    *agc_enabled.lock().unwrap() = false;
                     let mut gain = gain.lock().unwrap();
                     *gain -= 0.05;
                     if (*gain).is_sign_negative() {
                         *gain = 0.0;
                     }
                 }
+                'a' => {
+                    let mut agc_enabled = agc_enabled.lock().unwrap();
+                    *agc_enabled = !*agc_enabled;
+                    if *agc_enabled {
+                        println!("Automatic gain control on");
+                    } else {
+                        println!("Automatic gain control off");
+                    }
+                }
+                't' => tx_user_input.send(Message::Tap).unwrap(),
+                'T' => tx_user_input.send(Message::ResetTempo).unwrap(),
                 _ => (),
             }
         }
@@ -413,15 +932,124 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn data_callback(data: Vec<f32>, n_channels: usize, tx: &mpsc::Sender<Option<Vec<f32>>>) {
-    let mut samples = Vec::new();
-    for chunk in data.chunks_exact(n_channels) {
-        // max of samples from all channels
-        samples.push(
-            chunk
-                .iter()
-                .fold(f32::NEG_INFINITY, |acc, x| f32::max(acc, *x)),
+fn data_callback(
+    data: Vec<f32>,
+    n_channels: usize,
+    stereo: bool,
+    downmix: Downmix,
+    tx: &mpsc::Sender<Message>,
+) {
+    let samples = if stereo {
+        // the first two channels as left and right, a mono input is used as both
+        (0..2)
+            .map(|channel| {
+                data.chunks_exact(n_channels)
+                    .map(|chunk| chunk[channel.min(n_channels - 1)])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    } else {
+        vec![data
+            .chunks_exact(n_channels)
+            .map(|chunk| audio::downmix(chunk, downmix))
+            .collect::<Vec<_>>()]
+    };
+    tx.send(Message::Samples {
+        input: data,
+        visualized: samples,
+    })
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input and visualized channels that data_callback sends for the interleaved samples
+    fn callback_output(
+        data: &[f32],
+        n_channels: usize,
+        stereo: bool,
+        downmix: Downmix,
+    ) -> (Vec<f32>, Vec<Vec<f32>>) {
+        let (tx, rx) = mpsc::channel();
+        data_callback(data.to_vec(), n_channels, stereo, downmix, &tx);
+        match rx.try_recv() {
+            Ok(Message::Samples { input, visualized }) => (input, visualized),
+            _ => panic!("data_callback didn't send the samples"),
+        }
+    }
+
+    fn downmixed(data: &[f32], n_channels: usize, downmix: Downmix) -> Vec<f32> {
+        let (input, visualized) = callback_output(data, n_channels, false, downmix);
+        assert_eq!(input, data);
+        assert_eq!(visualized.len(), 1);
+        visualized.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn mono_input() {
+        let data = [0.5, -0.25, 0.0, 1.0];
+        for downmix in [
+            Downmix::Mean,
+            Downmix::Rms,
+            Downmix::Mid,
+            Downmix::Channel(0),
+        ] {
+            assert_eq!(downmixed(&data, 1, downmix), data, "{:?}", downmix);
+        }
+        assert_eq!(downmixed(&data, 1, Downmix::Side), [0.0; 4]);
+    }
+
+    #[test]
+    fn stereo_input() {
+        // (L, R) frames: in phase, in opposite phase, only left, only right
+        let data = [0.5, 0.5, 0.5, -0.5, 0.8, 0.0, 0.0, -0.6];
+        assert_eq!(downmixed(&data, 2, Downmix::Mean), [0.5, 0.0, 0.4, -0.3]);
+        assert_eq!(downmixed(&data, 2, Downmix::Mid), [0.5, 0.0, 0.4, -0.3]);
+        assert_eq!(downmixed(&data, 2, Downmix::Side), [0.0, 0.5, 0.4, 0.3]);
+        assert_eq!(
+            downmixed(&data, 2, Downmix::Channel(1)),
+            [0.5, -0.5, 0.0, -0.6]
         );
+        let rms = downmixed(&data, 2, Downmix::Rms);
+        let expected = [0.5, 0.5, 0.8 / 2f32.sqrt(), -0.6 / 2f32.sqrt()];
+        for (x, y) in rms.iter().zip(expected.iter()) {
+            assert!((x - y).abs() < 1e-6, "{:?} != {:?}", rms, expected);
+        }
+    }
+
+    #[test]
+    fn six_channel_input() {
+        // 5.1 frames with the channels numbered 1 to 6, then negated
+        let data = [
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, -1.0, -2.0, -3.0, -4.0, -5.0, -6.0,
+        ];
+        assert_eq!(downmixed(&data, 6, Downmix::Mean), [3.5, -3.5]);
+        assert_eq!(downmixed(&data, 6, Downmix::Mid), [1.5, -1.5]);
+        assert_eq!(downmixed(&data, 6, Downmix::Side), [-0.5, 0.5]);
+        assert_eq!(downmixed(&data, 6, Downmix::Channel(3)), [4.0, -4.0]);
+        let rms = downmixed(&data, 6, Downmix::Rms);
+        let expected = (91f32 / 6.0).sqrt();
+        assert!((rms[0] - expected).abs() < 1e-5 && (rms[1] + expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn stereo_mode_splits_left_and_right() {
+        let mono = [0.1, 0.2, 0.3];
+        let (_, visualized) = callback_output(&mono, 1, true, Downmix::Mean);
+        assert_eq!(visualized, [mono.to_vec(), mono.to_vec()]);
+
+        let six_channels = [
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0,
+        ];
+        let (input, visualized) = callback_output(&six_channels, 6, true, Downmix::Mean);
+        assert_eq!(input, six_channels);
+        assert_eq!(visualized, [vec![1.0, 7.0], vec![2.0, 8.0]]);
+    }
+
+    #[test]
+    fn incomplete_frame_is_dropped() {
+        assert_eq!(downmixed(&[1.0, 3.0, 5.0], 2, Downmix::Mean), [2.0]);
     }
-    tx.send(Some(samples)).unwrap();
 }
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+// how far back (in seconds) the adaptive threshold looks
+const THRESHOLD_WINDOW: f32 = 0.5;
+// spectral flux (per bin) below which nothing is considered an onset, so that silence doesn't trigger flashes
+const MIN_FLUX: f32 = 1e-3;
+
+/// Onset detector based on spectral flux compared against an adaptive threshold
+#[derive(Debug)]
+pub struct OnsetDetector {
+    previous_spectrum: Vec<f32>,
+    flux_history: VecDeque<f32>,
+    history_len: usize,
+    threshold_multiplier: f32,
+    refractory_frames: usize,
+    frames_since_onset: usize,
+    flux: f32,
+}
+
+impl OnsetDetector {
+    /// Return a detector for frames analyzed every frame_duration seconds,
+    /// sensitivity is between 0 and 1, refractory_time is in seconds
+    pub fn new(frame_duration: f32, sensitivity: f32, refractory_time: f32) -> Self {
+        let history_len = ((THRESHOLD_WINDOW / frame_duration).ceil() as usize).max(3);
+        let refractory_frames = (refractory_time / frame_duration).round() as usize;
+
+        OnsetDetector {
+            previous_spectrum: Vec::new(),
+            flux_history: VecDeque::with_capacity(history_len + 1),
+            history_len,
+            // the flux has to exceed the recent mean by this many standard deviations
+            threshold_multiplier: 4.0 * (1.0 - sensitivity.clamp(0.0, 1.0)) + 0.5,
+            refractory_frames,
+            frames_since_onset: usize::MAX,
+            flux: 0.0,
+        }
+    }
+
+    /// Spectral flux of the last frame, usable as an onset strength envelope
+    pub fn flux(&self) -> f32 {
+        self.flux
+    }
+
+    /// Feed the spectrum of the next frame, returning the strength (between 0 and 1) of the onset
+    /// if one happened in this frame
+    pub fn detect(&mut self, spectrum: &[f32]) -> Option<f32> {
+        if self.previous_spectrum.len() != spectrum.len() {
+            self.previous_spectrum = spectrum.to_vec();
+            return None;
+        }
+        // sum of increases in amplitude (decreases are ignored, they're not onsets)
+        let flux = spectrum
+            .iter()
+            .zip(self.previous_spectrum.iter())
+            .map(|(cur, prev)| (cur - prev).max(0.0))
+            .sum::<f32>()
+            / (spectrum.len() as f32);
+        self.previous_spectrum.copy_from_slice(spectrum);
+        self.flux = flux;
+
+        let n = self.flux_history.len() as f32;
+        let mean = self.flux_history.iter().sum::<f32>() / n.max(1.0);
+        let variance = self
+            .flux_history
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<f32>()
+            / n.max(1.0);
+        let threshold = mean + self.threshold_multiplier * variance.sqrt();
+
+        self.flux_history.push_back(flux);
+        if self.flux_history.len() > self.history_len {
+            self.flux_history.pop_front();
+        }
+        self.frames_since_onset = self.frames_since_onset.saturating_add(1);
+
+        let history_full = self.flux_history.len() == self.history_len;
+        if history_full
+            && flux > threshold
+            && flux > MIN_FLUX
+            && self.frames_since_onset > self.refractory_frames
+        {
+            self.frames_since_onset = 0;
+            // how much the flux stands out from what's been typical recently
+            Some(((flux - mean) / flux).clamp(0.0, 1.0))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::source::AudioSource;
+use std::collections::BTreeMap;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const RTP_VERSION: u8 = 2;
+const HEADER_LEN: usize = 12;
+// so that the packets don't get fragmented on any common network
+const MAX_PAYLOAD_LEN: usize = 1200;
+// payload types of L16 assigned in RFC 3551, any other rate or number of channels uses a dynamic one
+const PT_L16_STEREO: u8 = 10;
+const PT_L16_MONO: u8 = 11;
+const PT_DYNAMIC: u8 = 96;
+const PT_DYNAMIC_RANGE: std::ops::RangeInclusive<u8> = 96..=127;
+// at most this many lost packets in a row are replaced with silence, longer gaps are skipped
+const MAX_CONCEALED_PACKETS: u64 = 8;
+
+/// Values that differ between the runs of the sender, as RFC 3550 asks for the SSRC and the initial
+/// sequence number and timestamp (SplitMix64 seeded with the time and the process ID)
+#[derive(Debug)]
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        Random(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) as u32
+    }
+}
+
+/// Payload type, sequence number, SSRC and payload of an RTP packet, None if it's malformed
+pub fn parse_packet(packet: &[u8]) -> Option<(u8, u16, u32, &[u8])> {
+    if packet.len() < HEADER_LEN || packet[0] >> 6 != RTP_VERSION {
+        return None;
+    }
+    let has_padding = packet[0] & 0x20 != 0;
+    let has_extension = packet[0] & 0x10 != 0;
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let payload_type = packet[1] & 0x7f;
+    let seq = u16::from_be_bytes([packet[2], packet[3]]);
+    let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+
+    let mut start = HEADER_LEN + 4 * csrc_count;
+    if has_extension {
+        let ext_len = packet.get(start + 2..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([ext_len[0], ext_len[1]]) as usize;
+    }
+    let mut end = packet.len();
+    if has_padding {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    packet
+        .get(start..end)
+        .map(|payload| (payload_type, seq, ssrc, payload))
+}
+
+/// Why packets of the payload type can't be L16 of the given sample rate and number of channels,
+/// None if they can (dynamic payload types could be anything)
+pub fn payload_type_mismatch(
+    payload_type: u8,
+    sample_rate: u32,
+    n_channels: usize,
+) -> Option<String> {
+    let (pt_sample_rate, pt_n_channels) = match payload_type {
+        PT_L16_STEREO => (44100, 2),
+        PT_L16_MONO => (44100, 1),
+        pt if PT_DYNAMIC_RANGE.contains(&pt) => return None,
+        pt => return Some(format!("RTP payload type {} isn't L16 audio", pt)),
+    };
+    ((pt_sample_rate, pt_n_channels) != (sample_rate, n_channels)).then(|| {
+        format!(
+            "RTP payload type {} is L16 at {} Hz with {} channel(s), but {} Hz with {} channel(s) were expected \
+             (see --rate and --channels)",
+            payload_type, pt_sample_rate, pt_n_channels, sample_rate, n_channels
+        )
+    })
+}
+
+/// Decode an L16 payload (big-endian 16-bit samples), dropping a trailing incomplete frame
+pub fn decode_l16(payload: &[u8], n_channels: usize) -> Vec<f32> {
+    let frame_bytes = 2 * n_channels;
+    payload[..payload.len() - payload.len() % frame_bytes]
+        .chunks_exact(2)
+        .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0)
+        .collect::<Vec<_>>()
+}
+
+/// Reorders the received packets and holds them back for a while, so that late ones still make it in time
+#[derive(Debug)]
+pub struct JitterBuffer {
+    // decoded samples, by extended (not wrapping around) sequence number
+    packets: BTreeMap<u64, Vec<f32>>,
+    // extended sequence number of the next packet to be played out
+    next_seq: Option<u64>,
+    ssrc: Option<u32>,
+    // number of samples held in the buffer, and how many to hold before playing them out
+    n_buffered: usize,
+    delay: usize,
+    // length of the most recent packet, used for the silence replacing lost ones
+    packet_len: usize,
+    n_lost: u64,
+}
+
+impl JitterBuffer {
+    /// Return a buffer delaying the samples by delay samples (of all channels)
+    pub fn new(delay: usize) -> Self {
+        JitterBuffer {
+            packets: BTreeMap::new(),
+            next_seq: None,
+            ssrc: None,
+            n_buffered: 0,
+            delay,
+            packet_len: 0,
+            n_lost: 0,
+        }
+    }
+
+    /// Number of packets lost since the buffer was created
+    pub fn n_lost(&self) -> u64 {
+        self.n_lost
+    }
+
+    /// Add a received packet, packets arriving after their turn and duplicates are dropped
+    pub fn push(&mut self, seq: u16, ssrc: u32, samples: Vec<f32>) {
+        // a different SSRC means that the sender has been restarted
+        if self.ssrc != Some(ssrc) {
+            self.reset();
+            self.ssrc = Some(ssrc);
+        }
+        let next_seq = *self.next_seq.get_or_insert(seq as u64);
+        let offset = seq.wrapping_sub(next_seq as u16) as i16;
+        if offset < 0 || self.packets.contains_key(&(next_seq + offset as u64)) {
+            return;
+        }
+        self.packet_len = samples.len();
+        self.n_buffered += samples.len();
+        self.packets.insert(next_seq + offset as u64, samples);
+    }
+
+    /// Take the samples of the next packet out of the buffer once enough of them are buffered, or regardless
+    /// of that if flush is set, lost packets are replaced with silence
+    pub fn pop(&mut self, flush: bool) -> Option<Vec<f32>> {
+        if self.n_buffered == 0 || (!flush && self.n_buffered <= self.delay) {
+            return None;
+        }
+        let next_seq = self.next_seq?;
+        let (&seq, _) = self.packets.first_key_value()?;
+        if seq > next_seq {
+            let n_lost = seq - next_seq;
+            self.n_lost += n_lost;
+            self.next_seq = Some(seq);
+            return Some(vec![
+                0.0;
+                self.packet_len
+                    * n_lost.min(MAX_CONCEALED_PACKETS) as usize
+            ]);
+        }
+        let samples = self.packets.remove(&seq)?;
+        self.n_buffered -= samples.len();
+        self.next_seq = Some(seq + 1);
+
+        Some(samples)
+    }
+
+    /// Forget about the current stream, e.g. after all of it has been flushed out
+    pub fn reset(&mut self) {
+        self.packets.clear();
+        self.next_seq = None;
+        self.ssrc = None;
+        self.n_buffered = 0;
+    }
+}
+
+/// Splits interleaved samples into L16 RTP packets
+#[derive(Debug)]
+struct Packetizer {
+    payload_type: u8,
+    ssrc: u32,
+    seq: u16,
+    timestamp: u32,
+    n_channels: usize,
+    frames_per_packet: usize,
+    // samples left over from the previous call, not enough to fill a packet
+    pending: Vec<f32>,
+}
+
+impl Packetizer {
+    fn new(sample_rate: u32, n_channels: usize, random: &mut Random) -> Self {
+        let payload_type = match (sample_rate, n_channels) {
+            (44100, 2) => PT_L16_STEREO,
+            (44100, 1) => PT_L16_MONO,
+            _ => PT_DYNAMIC,
+        };
+        Packetizer {
+            payload_type,
+            ssrc: random.next(),
+            seq: random.next() as u16,
+            timestamp: random.next(),
+            n_channels,
+            frames_per_packet: (MAX_PAYLOAD_LEN / (2 * n_channels)).max(1),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Packets filled with the samples, the remaining ones are kept for the next call
+    fn push(&mut self, samples: &[f32]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(samples);
+        let packet_samples = self.frames_per_packet * self.n_channels;
+        let n_packets = self.pending.len() / packet_samples;
+        let packets = self.pending[..n_packets * packet_samples]
+            .chunks_exact(packet_samples)
+            .map(|chunk| {
+                let mut packet = Vec::with_capacity(HEADER_LEN + 2 * chunk.len());
+                packet.extend_from_slice(&[RTP_VERSION << 6, self.payload_type]);
+                packet.extend_from_slice(&self.seq.to_be_bytes());
+                packet.extend_from_slice(&self.timestamp.to_be_bytes());
+                packet.extend_from_slice(&self.ssrc.to_be_bytes());
+                for x in chunk {
+                    packet
+                        .extend_from_slice(&((x.clamp(-1.0, 1.0) * 32767.0) as i16).to_be_bytes());
+                }
+                self.seq = self.seq.wrapping_add(1);
+                self.timestamp = self.timestamp.wrapping_add(self.frames_per_packet as u32);
+                packet
+            })
+            .collect::<Vec<_>>();
+        self.pending.drain(..n_packets * packet_samples);
+
+        packets
+    }
+}
+
+/// Stream the input device to the address as L16 RTP, until the process is killed
+pub fn send(address: &str, device_name: &str) -> Result<(), anyhow::Error> {
+    let target = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::Error::msg(format!("Address '{}' couldn't be resolved", address)))?;
+    let socket = if target.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+    socket.connect(target)?;
+
+    let source = AudioSource::open_device(device_name)?;
+    let (sample_rate, n_channels) = (source.sample_rate(), source.n_channels());
+    let mut packetizer = Packetizer::new(sample_rate, n_channels, &mut Random::new());
+    let _source = source.start(move |data| {
+        for packet in packetizer.push(&data) {
+            // the receiver may not be up yet, so errors (e.g. refused connections) are ignored
+            let _ = socket.send(&packet);
+        }
+    })?;
+
+    println!(
+        "Streaming '{}' to {}, receive it with `audioleaf --listen <address> --rate {} --channels {}`",
+        device_name, target, sample_rate, n_channels
+    );
+    println!("Press Ctrl+C to stop");
+    loop {
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_round_trip() {
+        let mut packetizer = Packetizer::new(44100, 2, &mut Random::new());
+        let (first_seq, first_timestamp) = (packetizer.seq, packetizer.timestamp);
+        let samples = (0..3 * 600)
+            .map(|i| i as f32 / 4096.0 - 0.2)
+            .collect::<Vec<_>>();
+        let packets = packetizer.push(&samples);
+        assert_eq!(packets.len(), 3);
+        for (i, packet) in packets.iter().enumerate() {
+            let (payload_type, seq, ssrc, payload) = parse_packet(packet).unwrap();
+            assert_eq!(payload_type, PT_L16_STEREO);
+            assert_eq!(seq, first_seq.wrapping_add(i as u16));
+            assert_eq!(ssrc, packetizer.ssrc);
+            let decoded = decode_l16(payload, 2);
+            for (x, y) in decoded.iter().zip(samples[600 * i..].iter()) {
+                assert!((x - y).abs() < 1e-4);
+            }
+        }
+        let timestamp = u32::from_be_bytes(packets[1][4..8].try_into().unwrap());
+        assert_eq!(timestamp, first_timestamp.wrapping_add(300));
+    }
+
+    #[test]
+    fn initial_values_are_random() {
+        let mut random = Random::new();
+        let (a, b) = (
+            Packetizer::new(48000, 2, &mut random),
+            Packetizer::new(48000, 2, &mut random),
+        );
+        assert_ne!((a.ssrc, a.seq, a.timestamp), (b.ssrc, b.seq, b.timestamp));
+        assert_eq!(a.payload_type, PT_DYNAMIC);
+    }
+
+    #[test]
+    fn jitter_buffer_reorders_and_conceals_losses() {
+        let mut buffer = JitterBuffer::new(2);
+        let mut played = Vec::new();
+        // packet 65534 + 3 (wrapped around to 1) is lost, 0 arrives before 65535
+        for seq in [65533, 65534, 0, 65535, 2, 3] {
+            buffer.push(seq, 1, vec![seq as f32]);
+            while let Some(samples) = buffer.pop(false) {
+                played.extend(samples);
+            }
+        }
+        while let Some(samples) = buffer.pop(true) {
+            played.extend(samples);
+        }
+        assert_eq!(played, [65533.0, 65534.0, 65535.0, 0.0, 0.0, 2.0, 3.0]);
+        assert_eq!(buffer.n_lost(), 1);
+    }
+
+    #[test]
+    fn jitter_buffer_drops_late_packets_and_duplicates() {
+        let mut buffer = JitterBuffer::new(0);
+        buffer.push(10, 1, vec![1.0]);
+        assert_eq!(buffer.pop(false), Some(vec![1.0]));
+        buffer.push(9, 1, vec![2.0]);
+        buffer.push(10, 1, vec![3.0]);
+        assert_eq!(buffer.pop(true), None);
+        // a new SSRC starts a new stream
+        buffer.push(3, 2, vec![4.0]);
+        assert_eq!(buffer.pop(false), Some(vec![4.0]));
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        assert!(parse_packet(&[0x80, 10, 0, 1]).is_none());
+        // version 1
+        assert!(parse_packet(&[0x40, 10, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0]).is_none());
+        // padding longer than the packet
+        assert!(parse_packet(&[0xa0, 10, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 200]).is_none());
+        // one CSRC and 2 bytes of padding around a 2-byte payload
+        let packet = [
+            0xa1, 0x8a, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 9, 9, 9, 9, 0x12, 0x34, 0, 2,
+        ];
+        assert_eq!(parse_packet(&packet), Some((10, 1, 1, &[0x12, 0x34][..])));
+    }
+
+    #[test]
+    fn payload_type_must_match_the_format() {
+        assert!(payload_type_mismatch(PT_L16_STEREO, 44100, 2).is_none());
+        assert!(payload_type_mismatch(PT_L16_MONO, 44100, 1).is_none());
+        assert!(payload_type_mismatch(PT_DYNAMIC, 48000, 6).is_none());
+        assert!(payload_type_mismatch(PT_L16_STEREO, 48000, 2).is_some());
+        assert!(payload_type_mismatch(PT_L16_MONO, 44100, 2).is_some());
+        // PCMU
+        assert!(payload_type_mismatch(0, 8000, 1).is_some());
+    }
+}
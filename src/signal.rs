@@ -0,0 +1,210 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// peak amplitude of the signals, leaving some headroom
+const AMPLITUDE: f32 = 0.5;
+const DEFAULT_SWEEP: (f64, f64, f64) = (20.0, 20000.0, 10.0);
+const DEFAULT_TONE_SECS: f64 = 2.0;
+const DEFAULT_BPM: f64 = 120.0;
+// clicks are bursts of noise decaying with this time constant (in seconds), cut off after CLICK_SECS
+const CLICK_DECAY: f64 = 0.002;
+const CLICK_SECS: f64 = 0.01;
+
+/// Synthetic signal, given as one of:
+/// sweep[:from:to[:secs]], tones:f1,f2,...[:secs], chord:f1,f2,..., clicks[:bpm], white, pink
+#[derive(Debug, Clone)]
+pub enum TestSignal {
+    /// Sine sweeping exponentially from one frequency (in Hz) to another, over and over
+    Sweep {
+        from: f64,
+        to: f64,
+        secs: f64,
+    },
+    /// Sines at the frequencies (in Hz) one after another, each lasting secs
+    Tones {
+        freqs: Vec<f64>,
+        secs: f64,
+    },
+    /// Sines at all the frequencies (in Hz) at once
+    Chord {
+        freqs: Vec<f64>,
+    },
+    /// Click track at the tempo (in BPM)
+    Clicks {
+        bpm: f64,
+    },
+    White,
+    Pink,
+}
+
+impl TestSignal {
+    /// Highest frequency (in Hz) of the sines in the signal
+    pub fn max_freq(&self) -> Option<f64> {
+        match self {
+            TestSignal::Sweep { from, to, .. } => Some(from.max(*to)),
+            TestSignal::Tones { freqs, .. } | TestSignal::Chord { freqs } => {
+                freqs.iter().copied().reduce(f64::max)
+            }
+            TestSignal::Clicks { .. } | TestSignal::White | TestSignal::Pink => None,
+        }
+    }
+}
+
+fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("'{}' is not a positive number", value)),
+    }
+}
+
+fn parse_freqs(value: &str) -> Result<Vec<f64>, String> {
+    value.split(',').map(parse_positive).collect()
+}
+
+impl FromStr for TestSignal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        match parts[..] {
+            ["sweep"] => {
+                let (from, to, secs) = DEFAULT_SWEEP;
+                Ok(TestSignal::Sweep { from, to, secs })
+            }
+            ["sweep", from, to] => Ok(TestSignal::Sweep {
+                from: parse_positive(from)?,
+                to: parse_positive(to)?,
+                secs: DEFAULT_SWEEP.2,
+            }),
+            ["sweep", from, to, secs] => Ok(TestSignal::Sweep {
+                from: parse_positive(from)?,
+                to: parse_positive(to)?,
+                secs: parse_positive(secs)?,
+            }),
+            ["tones", freqs] => Ok(TestSignal::Tones {
+                freqs: parse_freqs(freqs)?,
+                secs: DEFAULT_TONE_SECS,
+            }),
+            ["tones", freqs, secs] => Ok(TestSignal::Tones {
+                freqs: parse_freqs(freqs)?,
+                secs: parse_positive(secs)?,
+            }),
+            ["chord", freqs] => Ok(TestSignal::Chord {
+                freqs: parse_freqs(freqs)?,
+            }),
+            ["clicks"] => Ok(TestSignal::Clicks { bpm: DEFAULT_BPM }),
+            ["clicks", bpm] => Ok(TestSignal::Clicks {
+                bpm: parse_positive(bpm)?,
+            }),
+            ["white"] => Ok(TestSignal::White),
+            ["pink"] => Ok(TestSignal::Pink),
+            _ => Err(format!(
+                "unknown test signal '{}', expected one of: sweep[:from:to[:secs]], tones:f1,f2,...[:secs], \
+                 chord:f1,f2,..., clicks[:bpm], white, pink",
+                s
+            )),
+        }
+    }
+}
+
+/// Xorshift generator of white noise between -1 and 1
+#[derive(Debug)]
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Value of a sine at the phase (in radians), advancing the phase by one sample at the frequency
+fn sine(phase: &mut f64, freq: f64, sample_rate: f64) -> f32 {
+    let value = phase.sin() as f32;
+    *phase = (*phase + 2.0 * PI * freq / sample_rate) % (2.0 * PI);
+    value
+}
+
+/// Produces the samples of a test signal, the same ones on every run
+#[derive(Debug)]
+pub struct Generator {
+    signal: TestSignal,
+    sample_rate: f64,
+    // index of the next sample
+    pos: u64,
+    // phases (in radians) of the sines
+    phases: Vec<f64>,
+    noise: Noise,
+    // state of the pinking filter
+    pink: [f32; 7],
+}
+
+impl Generator {
+    pub fn new(signal: TestSignal, sample_rate: u32) -> Self {
+        let n_phases = match &signal {
+            TestSignal::Chord { freqs } => freqs.len(),
+            _ => 1,
+        };
+        Generator {
+            signal,
+            sample_rate: sample_rate as f64,
+            pos: 0,
+            phases: vec![0.0; n_phases],
+            noise: Noise(0x9e3779b9),
+            pink: [0.0; 7],
+        }
+    }
+
+    /// Next sample of the signal
+    pub fn next_sample(&mut self) -> f32 {
+        let (sample_rate, phases, noise) = (self.sample_rate, &mut self.phases, &mut self.noise);
+        let t = self.pos as f64 / sample_rate;
+        self.pos += 1;
+        let value = match &self.signal {
+            &TestSignal::Sweep { from, to, secs } => {
+                let freq = from * (to / from).powf((t % secs) / secs);
+                sine(&mut phases[0], freq, sample_rate)
+            }
+            TestSignal::Tones { freqs, secs } => {
+                let freq = freqs[(t / secs) as usize % freqs.len()];
+                sine(&mut phases[0], freq, sample_rate)
+            }
+            TestSignal::Chord { freqs } => {
+                freqs
+                    .iter()
+                    .zip(phases.iter_mut())
+                    .map(|(&freq, phase)| sine(phase, freq, sample_rate))
+                    .sum::<f32>()
+                    / freqs.len() as f32
+            }
+            &TestSignal::Clicks { bpm } => {
+                let since_click = t % (60.0 / bpm);
+                if since_click < CLICK_SECS {
+                    noise.next() * (-since_click / CLICK_DECAY).exp() as f32
+                } else {
+                    0.0
+                }
+            }
+            TestSignal::White => noise.next(),
+            // Paul Kellet's refined pinking filter
+            TestSignal::Pink => {
+                let white = noise.next();
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                // roughly normalized to the range of white noise
+                pink * 0.11
+            }
+        };
+
+        AMPLITUDE * value
+    }
+}
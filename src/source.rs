@@ -0,0 +1,721 @@
+use crate::rtp::{self, JitterBuffer};
+use crate::signal::{Generator, TestSignal};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, InputCallbackInfo, SizedSample, StreamConfig};
+use std::fs::File;
+use std::io::Read;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// number of frames (samples of all channels) read from a raw PCM stream at once
+const PCM_BLOCK_FRAMES: usize = 1024;
+
+// how long to wait before trying to open a FIFO again after opening it failed
+const FIFO_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// the largest UDP datagram
+const MAX_DATAGRAM_LEN: usize = 65536;
+// once no packets have arrived for this long (on top of the jitter buffer's delay),
+// whatever is left in the jitter buffer gets played out
+const NETWORK_IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Encoding of the samples in a raw PCM stream
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum PcmFormat {
+    /// Signed 8-bit integers
+    S8,
+    /// Signed 16-bit little-endian integers
+    S16le,
+    /// Signed 24-bit little-endian integers, each padded to 32 bits
+    S24in32le,
+    /// Signed 32-bit little-endian integers
+    S32le,
+    /// 32-bit little-endian floats
+    F32le,
+}
+
+impl PcmFormat {
+    fn bytes_per_sample(&self) -> usize {
+        match self {
+            PcmFormat::S8 => 1,
+            PcmFormat::S16le => 2,
+            PcmFormat::S24in32le | PcmFormat::S32le | PcmFormat::F32le => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            PcmFormat::S8 => bytes[0] as i8 as f32 / 128.0,
+            PcmFormat::S16le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            PcmFormat::S24in32le => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 8388608.0
+            }
+            PcmFormat::S32le => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0
+            }
+            PcmFormat::F32le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Parse MPD's "rate:bits:channels" audio format (e.g. "44100:16:2", "48000:f:2") into the sample rate,
+/// the sample format and the number of channels
+pub fn parse_mpd_format(format: &str) -> Result<(u32, PcmFormat, usize), anyhow::Error> {
+    let invalid = || {
+        anyhow::Error::msg(format!(
+            "Invalid MPD audio format '{}', expected 'rate:bits:channels' with bits being 8, 16, 24, 32 or f",
+            format
+        ))
+    };
+    let parts = format.split(':').collect::<Vec<_>>();
+    let [rate, bits, channels] = parts[..] else {
+        return Err(invalid());
+    };
+    let sample_rate = rate.parse::<u32>().map_err(|_| invalid())?;
+    // MPD writes samples in the host's byte order, which is little-endian on all platforms it's run on in practice
+    let pcm_format = match bits {
+        "8" => PcmFormat::S8,
+        "16" => PcmFormat::S16le,
+        "24" => PcmFormat::S24in32le,
+        "32" => PcmFormat::S32le,
+        "f" => PcmFormat::F32le,
+        _ => return Err(invalid()),
+    };
+    let n_channels = channels.parse::<usize>().map_err(|_| invalid())?;
+    if sample_rate == 0 || n_channels == 0 {
+        return Err(invalid());
+    }
+
+    Ok((sample_rate, pcm_format, n_channels))
+}
+
+/// An opened (but not yet started) source of audio
+pub enum AudioSource {
+    /// A live input device
+    Device {
+        device: cpal::Device,
+        config: cpal::SupportedStreamConfig,
+    },
+    /// An audio file, decoded in its own thread
+    File {
+        path: PathBuf,
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+        sample_rate: u32,
+        n_channels: usize,
+        realtime: bool,
+    },
+    /// Interleaved raw PCM samples written to a named pipe by MPD's fifo output
+    MpdFifo {
+        path: PathBuf,
+        format: PcmFormat,
+        sample_rate: u32,
+        n_channels: usize,
+    },
+    /// L16 RTP packets received over UDP
+    Network {
+        socket: UdpSocket,
+        sample_rate: u32,
+        n_channels: usize,
+        jitter_delay: Duration,
+    },
+    /// Synthetic signal, the same in all channels
+    TestSignal {
+        signal: TestSignal,
+        sample_rate: u32,
+        n_channels: usize,
+    },
+    /// Interleaved raw PCM samples on the standard input
+    Stdin {
+        format: PcmFormat,
+        sample_rate: u32,
+        n_channels: usize,
+        realtime: bool,
+    },
+}
+
+/// A started source, delivering samples until it's dropped (or until it runs out of them)
+pub struct RunningSource {
+    // input devices stop delivering samples once their stream is dropped
+    _stream: Option<cpal::Stream>,
+}
+
+impl AudioSource {
+    /// Open the input device with the given name ("default" being the system's default one)
+    pub fn open_device(device_name: &str) -> Result<Self, anyhow::Error> {
+        let host = cpal::default_host();
+        let device = match device_name {
+            "default" => host.default_input_device(),
+            _ => host
+                .input_devices()?
+                .find(|x| x.name().map(|y| y == device_name).unwrap_or(false)),
+        };
+        let device = match device {
+            Some(device) => device,
+            None => {
+                return Err(anyhow::Error::msg(format!(
+                    "Input device '{}' not found, available input devices: {}",
+                    device_name,
+                    host.input_devices()?.fold(String::new(), |acc, dev| acc
+                        + &dev.name().unwrap_or_default()
+                        + ", ")
+                )));
+            }
+        };
+        let config = device.default_input_config()?;
+
+        Ok(AudioSource::Device { device, config })
+    }
+
+    /// Read from the named pipe of MPD's fifo output, with MPD's audio format given as "rate:bits:channels"
+    pub fn open_mpd_fifo(path: &Path, format: &str) -> Result<Self, anyhow::Error> {
+        let (sample_rate, format, n_channels) = parse_mpd_format(format)?;
+        if !Path::try_exists(path)? {
+            return Err(anyhow::Error::msg(format!(
+                "MPD FIFO '{}' not found",
+                path.to_string_lossy()
+            )));
+        }
+        // reading a regular file over and over again would replay it in a busy loop
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if !std::fs::metadata(path)?.file_type().is_fifo() {
+                return Err(anyhow::Error::msg(format!(
+                    "'{}' is not a named pipe, set the path of MPD's fifo output",
+                    path.to_string_lossy()
+                )));
+            }
+        }
+
+        Ok(AudioSource::MpdFifo {
+            path: path.to_path_buf(),
+            format,
+            sample_rate,
+            n_channels,
+        })
+    }
+
+    /// Receive L16 RTP on the address, holding the packets back by jitter_delay to let late ones catch up
+    pub fn open_network(
+        address: &str,
+        sample_rate: u32,
+        n_channels: usize,
+        jitter_delay: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        if sample_rate == 0 || n_channels == 0 {
+            return Err(anyhow::Error::msg(
+                "Sample rate and number of channels of the network stream must be positive",
+            ));
+        }
+        let socket = UdpSocket::bind(address)
+            .map_err(|e| anyhow::Error::msg(format!("Listening on '{}' failed: {}", address, e)))?;
+        socket.set_read_timeout(Some(jitter_delay + NETWORK_IDLE_TIMEOUT))?;
+
+        Ok(AudioSource::Network {
+            socket,
+            sample_rate,
+            n_channels,
+            jitter_delay,
+        })
+    }
+
+    /// Generate the signal at real-time pace
+    pub fn open_test_signal(
+        signal: TestSignal,
+        sample_rate: u32,
+        n_channels: usize,
+    ) -> Result<Self, anyhow::Error> {
+        if sample_rate == 0 || n_channels == 0 {
+            return Err(anyhow::Error::msg(
+                "Sample rate and number of channels of the test signal must be positive",
+            ));
+        }
+        if let Some(max_freq) = signal.max_freq() {
+            if max_freq >= sample_rate as f64 / 2.0 {
+                return Err(anyhow::Error::msg(format!(
+                    "Frequencies of the test signal must be below half the sample rate ({} Hz)",
+                    sample_rate as f64 / 2.0
+                )));
+            }
+        }
+
+        Ok(AudioSource::TestSignal {
+            signal,
+            sample_rate,
+            n_channels,
+        })
+    }
+
+    /// Read raw PCM from the standard input, paced like open_file
+    pub fn open_stdin(
+        format: PcmFormat,
+        sample_rate: u32,
+        n_channels: usize,
+        realtime: bool,
+    ) -> Result<Self, anyhow::Error> {
+        if sample_rate == 0 || n_channels == 0 {
+            return Err(anyhow::Error::msg(
+                "Sample rate and number of channels of the standard input must be positive",
+            ));
+        }
+
+        Ok(AudioSource::Stdin {
+            format,
+            sample_rate,
+            n_channels,
+            realtime,
+        })
+    }
+
+    /// Open an audio file (WAV, FLAC, Ogg Vorbis, ...), which will be played at real-time pace if realtime is set
+    /// or as fast as possible otherwise
+    pub fn open_file(path: &Path, realtime: bool) -> Result<Self, anyhow::Error> {
+        let failed = |e: &dyn std::fmt::Display| {
+            anyhow::Error::msg(format!(
+                "Opening '{}' failed: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        };
+        let file = File::open(path).map_err(|e| failed(&e))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| failed(&e))?
+            .format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| failed(&"no audio track found"))?;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| failed(&"unknown sample rate"))?;
+        let n_channels = track
+            .codec_params
+            .channels
+            .ok_or_else(|| failed(&"unknown channel layout"))?
+            .count();
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| failed(&e))?;
+        let track_id = track.id;
+
+        Ok(AudioSource::File {
+            path: path.to_path_buf(),
+            format,
+            decoder,
+            track_id,
+            sample_rate,
+            n_channels,
+            realtime,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        match self {
+            AudioSource::Device { config, .. } => config.sample_rate().0,
+            AudioSource::File { sample_rate, .. }
+            | AudioSource::MpdFifo { sample_rate, .. }
+            | AudioSource::Network { sample_rate, .. }
+            | AudioSource::TestSignal { sample_rate, .. }
+            | AudioSource::Stdin { sample_rate, .. } => *sample_rate,
+        }
+    }
+
+    pub fn n_channels(&self) -> usize {
+        match self {
+            AudioSource::Device { config, .. } => config.channels() as usize,
+            AudioSource::File { n_channels, .. }
+            | AudioSource::MpdFifo { n_channels, .. }
+            | AudioSource::Network { n_channels, .. }
+            | AudioSource::TestSignal { n_channels, .. }
+            | AudioSource::Stdin { n_channels, .. } => *n_channels,
+        }
+    }
+
+    /// Start delivering interleaved samples (of all channels) to the callback
+    pub fn start<F>(self, callback: F) -> Result<RunningSource, anyhow::Error>
+    where
+        F: FnMut(Vec<f32>) + Send + 'static,
+    {
+        match self {
+            AudioSource::Device { device, config } => {
+                let sample_format = config.sample_format();
+                let config: StreamConfig = config.into();
+                let stream = match sample_format {
+                    cpal::SampleFormat::F32 => build_stream::<f32, F>(&device, &config, callback),
+                    cpal::SampleFormat::F64 => build_stream::<f64, F>(&device, &config, callback),
+                    cpal::SampleFormat::I8 => build_stream::<i8, F>(&device, &config, callback),
+                    cpal::SampleFormat::I16 => build_stream::<i16, F>(&device, &config, callback),
+                    cpal::SampleFormat::I32 => build_stream::<i32, F>(&device, &config, callback),
+                    cpal::SampleFormat::I64 => build_stream::<i64, F>(&device, &config, callback),
+                    cpal::SampleFormat::U8 => build_stream::<u8, F>(&device, &config, callback),
+                    cpal::SampleFormat::U16 => build_stream::<u16, F>(&device, &config, callback),
+                    cpal::SampleFormat::U32 => build_stream::<u32, F>(&device, &config, callback),
+                    cpal::SampleFormat::U64 => build_stream::<u64, F>(&device, &config, callback),
+                    sample_format => {
+                        return Err(anyhow::Error::msg(format!(
+                            "Unsupported sample format: {}",
+                            sample_format
+                        )));
+                    }
+                }?;
+                stream.play()?;
+
+                Ok(RunningSource {
+                    _stream: Some(stream),
+                })
+            }
+            AudioSource::File {
+                path,
+                mut format,
+                mut decoder,
+                track_id,
+                sample_rate,
+                n_channels,
+                realtime,
+            } => {
+                let mut pacer = Pacer::new(sample_rate, n_channels, realtime);
+                let mut callback = callback;
+                thread::spawn(move || {
+                    loop {
+                        let packet = match format.next_packet() {
+                            Ok(packet) => packet,
+                            Err(DecodeError::IoError(e))
+                                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                            {
+                                break;
+                            }
+                            Err(e) => {
+                                eprintln!("Reading '{}' failed: {}", path.to_string_lossy(), e);
+                                break;
+                            }
+                        };
+                        if packet.track_id() != track_id {
+                            continue;
+                        }
+                        let decoded = match decoder.decode(&packet) {
+                            Ok(decoded) => decoded,
+                            // a corrupted packet, skip it
+                            Err(DecodeError::DecodeError(_)) => continue,
+                            Err(e) => {
+                                eprintln!("Decoding '{}' failed: {}", path.to_string_lossy(), e);
+                                break;
+                            }
+                        };
+                        let mut samples =
+                            SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                        samples.copy_interleaved_ref(decoded);
+                        pacer.wait(samples.samples().len());
+                        callback(samples.samples().to_vec());
+                    }
+                    println!("Reached the end of '{}'", path.to_string_lossy());
+                });
+
+                Ok(RunningSource { _stream: None })
+            }
+            AudioSource::MpdFifo {
+                path,
+                format,
+                sample_rate,
+                n_channels,
+            } => {
+                let mut callback = callback;
+                thread::spawn(move || loop {
+                    // blocks until MPD opens the FIFO for writing
+                    let fifo = match File::open(&path) {
+                        Ok(fifo) => fifo,
+                        Err(e) => {
+                            eprintln!("Opening '{}' failed: {}", path.to_string_lossy(), e);
+                            thread::sleep(FIFO_RETRY_INTERVAL);
+                            continue;
+                        }
+                    };
+                    // MPD paces the samples itself
+                    let pacer = Pacer::new(sample_rate, n_channels, false);
+                    // the end of the stream only means that MPD has closed the FIFO (e.g. between tracks),
+                    // so it's opened again
+                    if let Err(e) = read_pcm(fifo, format, n_channels, pacer, &mut callback) {
+                        eprintln!("Reading '{}' failed: {}", path.to_string_lossy(), e);
+                        thread::sleep(FIFO_RETRY_INTERVAL);
+                    }
+                });
+
+                Ok(RunningSource { _stream: None })
+            }
+            AudioSource::Network {
+                socket,
+                sample_rate,
+                n_channels,
+                jitter_delay,
+            } => {
+                let delay = (jitter_delay.as_secs_f64() * sample_rate as f64) as usize * n_channels;
+                let mut jitter_buffer = JitterBuffer::new(delay);
+                let mut callback = callback;
+                thread::spawn(move || {
+                    let mut buf = vec![0; MAX_DATAGRAM_LEN];
+                    // stream (SSRC and payload type) of the previous packet and whether it can be played,
+                    // so that a mismatch is reported once per stream
+                    let mut last_checked = None;
+                    loop {
+                        let flush = match socket.recv(&mut buf) {
+                            Ok(len) => {
+                                if let Some((payload_type, seq, ssrc, payload)) =
+                                    rtp::parse_packet(&buf[..len])
+                                {
+                                    let stream = (ssrc, payload_type);
+                                    if last_checked.map(|(last_stream, _)| last_stream)
+                                        != Some(stream)
+                                    {
+                                        let mismatch = rtp::payload_type_mismatch(
+                                            payload_type,
+                                            sample_rate,
+                                            n_channels,
+                                        );
+                                        if let Some(mismatch) = &mismatch {
+                                            eprintln!("{}, skipping its packets", mismatch);
+                                        }
+                                        last_checked = Some((stream, mismatch.is_none()));
+                                    }
+                                    // samples in another format would only be visualized as noise
+                                    if last_checked.is_some_and(|(_, playable)| !playable) {
+                                        continue;
+                                    }
+                                    let samples = rtp::decode_l16(payload, n_channels);
+                                    jitter_buffer.push(seq, ssrc, samples);
+                                }
+                                false
+                            }
+                            Err(e)
+                                if matches!(
+                                    e.kind(),
+                                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                                ) =>
+                            {
+                                true
+                            }
+                            Err(e) => {
+                                eprintln!("Receiving audio failed: {}", e);
+                                thread::sleep(NETWORK_IDLE_TIMEOUT);
+                                continue;
+                            }
+                        };
+                        let n_lost = jitter_buffer.n_lost();
+                        while let Some(samples) = jitter_buffer.pop(flush) {
+                            callback(samples);
+                        }
+                        if jitter_buffer.n_lost() > n_lost {
+                            eprintln!(
+                                "Lost {} packet(s), {} in total",
+                                jitter_buffer.n_lost() - n_lost,
+                                jitter_buffer.n_lost()
+                            );
+                        }
+                        // the stream has stopped, the next packet may well start a new one
+                        if flush {
+                            jitter_buffer.reset();
+                        }
+                    }
+                });
+
+                Ok(RunningSource { _stream: None })
+            }
+            AudioSource::TestSignal {
+                signal,
+                sample_rate,
+                n_channels,
+            } => {
+                let mut generator = Generator::new(signal, sample_rate);
+                // the signal never ends, so it's always generated at real-time pace
+                let mut pacer = Pacer::new(sample_rate, n_channels, true);
+                let mut callback = callback;
+                thread::spawn(move || loop {
+                    let mut samples = Vec::with_capacity(PCM_BLOCK_FRAMES * n_channels);
+                    for _ in 0..PCM_BLOCK_FRAMES {
+                        let sample = generator.next_sample();
+                        samples.extend(std::iter::repeat_n(sample, n_channels));
+                    }
+                    pacer.wait(samples.len());
+                    callback(samples);
+                });
+
+                Ok(RunningSource { _stream: None })
+            }
+            AudioSource::Stdin {
+                format,
+                sample_rate,
+                n_channels,
+                realtime,
+            } => {
+                let pacer = Pacer::new(sample_rate, n_channels, realtime);
+                thread::spawn(move || {
+                    if let Err(e) =
+                        read_pcm(std::io::stdin().lock(), format, n_channels, pacer, callback)
+                    {
+                        eprintln!("Reading the standard input failed: {}", e);
+                    }
+                    println!("Reached the end of the standard input");
+                });
+
+                Ok(RunningSource { _stream: None })
+            }
+        }
+    }
+}
+
+/// Read interleaved raw PCM samples until the end of the stream, delivering them to the callback
+fn read_pcm<R, F>(
+    mut reader: R,
+    format: PcmFormat,
+    n_channels: usize,
+    mut pacer: Pacer,
+    mut callback: F,
+) -> Result<(), std::io::Error>
+where
+    R: Read,
+    F: FnMut(Vec<f32>),
+{
+    let frame_bytes = n_channels * format.bytes_per_sample();
+    let mut buf = vec![0; PCM_BLOCK_FRAMES * frame_bytes];
+    // bytes left over from the previous read, which ended in the middle of a frame
+    let mut pending = 0;
+    loop {
+        let n_read = match reader.read(&mut buf[pending..]) {
+            Ok(0) => return Ok(()),
+            Ok(n_read) => n_read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let n_bytes = pending + n_read;
+        let whole_bytes = n_bytes - n_bytes % frame_bytes;
+        let samples = buf[..whole_bytes]
+            .chunks_exact(format.bytes_per_sample())
+            .map(|bytes| format.decode(bytes))
+            .collect::<Vec<_>>();
+        buf.copy_within(whole_bytes..n_bytes, 0);
+        pending = n_bytes - whole_bytes;
+        if !samples.is_empty() {
+            pacer.wait(samples.len());
+            callback(samples);
+        }
+    }
+}
+
+fn build_stream<T, F>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut callback: F,
+) -> Result<cpal::Stream, anyhow::Error>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    F: FnMut(Vec<f32>) + Send + 'static,
+{
+    let error_callback =
+        move |err| eprintln!("An error has occured while playing the stream: {}", err);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &InputCallbackInfo| {
+            callback(Vec::from_iter(
+                data.iter().map(|sample| sample.to_sample::<f32>()),
+            ))
+        },
+        error_callback,
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+/// Keeps a source that could deliver samples faster from getting ahead of real time
+#[derive(Debug)]
+pub struct Pacer {
+    start: Option<Instant>,
+    delivered: u64,
+    samples_per_sec: f64,
+    realtime: bool,
+}
+
+impl Pacer {
+    pub fn new(sample_rate: u32, n_channels: usize, realtime: bool) -> Self {
+        Pacer {
+            start: None,
+            delivered: 0,
+            samples_per_sec: sample_rate as f64 * n_channels as f64,
+            realtime,
+        }
+    }
+
+    /// Wait until the next n_samples (interleaved) samples can be delivered
+    pub fn wait(&mut self, n_samples: usize) {
+        if !self.realtime {
+            return;
+        }
+        // just like an input device, samples are delivered once all of them have been "played"
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.delivered += n_samples as u64;
+        let due = start + Duration::from_secs_f64(self.delivered as f64 / self.samples_per_sec);
+        if let Some(left) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(left);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mpd_format_is_parsed() {
+        for (format, expected) in [
+            ("44100:16:2", (44100, 2)),
+            ("48000:24:2", (48000, 2)),
+            ("96000:f:6", (96000, 6)),
+            ("22050:8:1", (22050, 1)),
+        ] {
+            let (sample_rate, _, n_channels) = parse_mpd_format(format).unwrap();
+            assert_eq!((sample_rate, n_channels), expected);
+        }
+        for format in [
+            "*:16:2",
+            "44100:*:2",
+            "44100:12:2",
+            "0:16:2",
+            "44100:16:0",
+            "44100:16",
+        ] {
+            assert!(parse_mpd_format(format).is_err(), "{}", format);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mpd_fifo_must_be_a_named_pipe() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("audioleaf-not-a-fifo-{}", std::process::id()));
+        std::fs::write(&file, [0; 16]).unwrap();
+        assert!(AudioSource::open_mpd_fifo(&file, "44100:16:2").is_err());
+        assert!(AudioSource::open_mpd_fifo(&dir, "44100:16:2").is_err());
+        std::fs::remove_file(&file).unwrap();
+    }
+}
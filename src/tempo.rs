@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+// how much (in seconds) of the onset strength envelope is used for estimating the tempo
+const ENVELOPE_WINDOW: f32 = 8.0;
+// how often (in seconds) the tempo is estimated again
+const ESTIMATE_INTERVAL: f32 = 1.0;
+// tempos around this one (in BPM) are preferred when the autocorrelation is ambiguous
+const PREFERRED_BPM: f32 = 120.0;
+// how much a single estimate or onset moves the tracked period and phase
+const PERIOD_ADAPTATION: f32 = 0.25;
+// estimates differing from the tracked period by more than this (relatively) are a tempo change
+// (or an octave error) and only replace the period once two of them in a row agree
+const PERIOD_TOLERANCE: f32 = 0.1;
+const PHASE_ADAPTATION: f32 = 0.2;
+// taps further apart than this (in seconds) start a new sequence of taps
+const MAX_TAP_INTERVAL: f32 = 2.0;
+const BEATS_PER_BAR: usize = 4;
+// how much the onsets on a single beat move the strength remembered for its position in the bar
+const BAR_ADAPTATION: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Beat {
+    /// Whether this is the first beat of a bar (assuming 4/4 time), taken to be the position in the bar
+    /// whose beats have had the strongest onsets
+    pub downbeat: bool,
+}
+
+/// Tempo estimator working on an onset strength envelope, with a tracker predicting when the next beat comes
+#[derive(Debug)]
+pub struct TempoTracker {
+    frame_duration: f32,
+    envelope: VecDeque<f32>,
+    envelope_len: usize,
+    min_lag: usize,
+    max_lag: usize,
+    frames_since_estimate: usize,
+    estimate_interval: usize,
+    // both in frames
+    period: Option<f32>,
+    candidate_period: Option<f32>,
+    next_beat: f32,
+    beat_count: usize,
+    // smoothed onset strength on the beats at each position in the bar
+    beat_strengths: [f32; BEATS_PER_BAR],
+    frame_no: usize,
+    taps: Vec<usize>,
+    tapped: bool,
+}
+
+impl TempoTracker {
+    /// Return a tracker for frames analyzed every frame_duration seconds, looking for tempos between min_bpm and max_bpm
+    pub fn new(frame_duration: f32, min_bpm: f32, max_bpm: f32) -> Self {
+        let envelope_len = (ENVELOPE_WINDOW / frame_duration).ceil() as usize;
+        let lag_of = |bpm: f32| 60.0 / (bpm * frame_duration);
+        let max_lag = (lag_of(min_bpm).ceil() as usize)
+            .min(envelope_len / 2)
+            .max(2);
+        let min_lag = (lag_of(max_bpm).floor() as usize).clamp(2, max_lag);
+
+        TempoTracker {
+            frame_duration,
+            envelope: VecDeque::with_capacity(envelope_len + 1),
+            envelope_len,
+            min_lag,
+            max_lag,
+            frames_since_estimate: 0,
+            estimate_interval: (ESTIMATE_INTERVAL / frame_duration).ceil() as usize,
+            period: None,
+            candidate_period: None,
+            next_beat: 0.0,
+            beat_count: 0,
+            beat_strengths: [0.0; BEATS_PER_BAR],
+            frame_no: 0,
+            taps: Vec::new(),
+            tapped: false,
+        }
+    }
+
+    /// The current tempo estimate in BPM
+    pub fn bpm(&self) -> Option<f32> {
+        self.period
+            .map(|period| 60.0 / (period * self.frame_duration))
+    }
+
+    /// Feed the onset strength of the next frame and whether an onset was detected in it,
+    /// returning a beat if one is predicted to fall on this frame
+    pub fn update(&mut self, onset_strength: f32, onset: bool) -> Option<Beat> {
+        self.frame_no += 1;
+        self.envelope.push_back(onset_strength);
+        if self.envelope.len() > self.envelope_len {
+            self.envelope.pop_front();
+        }
+        self.frames_since_estimate += 1;
+        if !self.tapped
+            && self.frames_since_estimate >= self.estimate_interval
+            && self.envelope.len() == self.envelope_len
+        {
+            self.frames_since_estimate = 0;
+            if let Some(estimate) = self.estimate_period() {
+                let agrees = |period: f32| (estimate / period - 1.0).abs() < PERIOD_TOLERANCE;
+                match self.period {
+                    Some(period) if agrees(period) => {
+                        self.period = Some(period + PERIOD_ADAPTATION * (estimate - period));
+                        self.candidate_period = None;
+                    }
+                    Some(_) if !self.candidate_period.is_some_and(agrees) => {
+                        self.candidate_period = Some(estimate);
+                    }
+                    _ => {
+                        self.period = Some(estimate);
+                        self.candidate_period = None;
+                    }
+                }
+            }
+        }
+
+        let period = self.period?;
+        if onset {
+            // the predicted beat closest to now, relative to the current frame (negative if it's already passed)
+            let offset = if self.next_beat < period / 2.0 {
+                self.next_beat
+            } else {
+                self.next_beat - period
+            };
+            if offset.abs() < period / 4.0 {
+                self.next_beat -= PHASE_ADAPTATION * offset;
+            }
+        }
+
+        self.next_beat -= 1.0;
+        if self.next_beat <= 0.0 {
+            self.next_beat += period;
+            Some(self.next())
+        } else {
+            None
+        }
+    }
+
+    /// Register a tap on the current frame, once there are at least two taps in a row
+    /// their tempo overrides the estimated one and a beat falls on the tap
+    pub fn tap(&mut self) -> Option<Beat> {
+        let max_interval = (MAX_TAP_INTERVAL / self.frame_duration) as usize;
+        if self
+            .taps
+            .last()
+            .is_some_and(|&last| self.frame_no - last > max_interval)
+        {
+            self.taps.clear();
+        }
+        self.taps.push(self.frame_no);
+        if self.taps.len() < 2 {
+            return None;
+        }
+
+        let (first, last) = (self.taps[0], *self.taps.last().unwrap());
+        let period = (last - first) as f32 / (self.taps.len() - 1) as f32;
+        if period < 1.0 {
+            return None;
+        }
+        self.period = Some(period);
+        self.next_beat = period;
+        self.tapped = true;
+
+        Some(self.next())
+    }
+
+    /// Go back to estimating the tempo from the audio
+    pub fn reset(&mut self) {
+        self.taps.clear();
+        self.tapped = false;
+    }
+
+    fn next(&mut self) -> Beat {
+        // the predicted beat can come a little after the onset it's locked to
+        let lookback = self
+            .period
+            .map_or(1, |period| (period / 4.0).ceil() as usize)
+            .max(1);
+        let strength = self
+            .envelope
+            .iter()
+            .rev()
+            .take(lookback)
+            .copied()
+            .fold(0.0, f32::max);
+        let position = self.beat_count % BEATS_PER_BAR;
+        self.beat_strengths[position] +=
+            BAR_ADAPTATION * (strength - self.beat_strengths[position]);
+        // the first of the strongest positions, so that the bar starts on the first beat until there are onsets
+        let strongest = (0..BEATS_PER_BAR).fold(0, |best, i| {
+            if self.beat_strengths[i] > self.beat_strengths[best] {
+                i
+            } else {
+                best
+            }
+        });
+        self.beat_count += 1;
+
+        Beat {
+            downbeat: position == strongest,
+        }
+    }
+
+    /// Period (in frames) at which the autocorrelation of the envelope peaks, weighted towards PREFERRED_BPM
+    fn estimate_period(&self) -> Option<f32> {
+        let n = self.envelope.len();
+        let mean = self.envelope.iter().sum::<f32>() / (n as f32);
+        let envelope = self.envelope.iter().map(|x| x - mean).collect::<Vec<_>>();
+        let preferred_lag = 60.0 / (PREFERRED_BPM * self.frame_duration);
+        let autocorrelation = (0..=(self.max_lag + 1))
+            .map(|lag| {
+                let r = (lag..n)
+                    .map(|t| envelope[t] * envelope[t - lag])
+                    .sum::<f32>()
+                    / ((n - lag) as f32);
+                // log-gaussian weighting, one octave away from the preferred tempo halves the score
+                let octaves = (lag as f32 / preferred_lag).log2();
+                r * (-octaves * octaves * std::f32::consts::LN_2).exp()
+            })
+            .collect::<Vec<_>>();
+
+        let best_lag = (self.min_lag..=self.max_lag).max_by(|&a, &b| {
+            autocorrelation[a]
+                .partial_cmp(&autocorrelation[b])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if autocorrelation[best_lag] <= 0.0 {
+            return None;
+        }
+        // parabolic interpolation around the peak for a fractional lag
+        let (left, peak, right) = (
+            autocorrelation[best_lag - 1],
+            autocorrelation[best_lag],
+            autocorrelation[best_lag + 1],
+        );
+        let denominator = left - 2.0 * peak + right;
+        let shift = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        Some(best_lag as f32 + shift)
+    }
+}